@@ -11,10 +11,18 @@ use graphics_3d::{
 
     Draw,
     DrawCommand,
-    Vertex,
-    Color
+    Color,
+    Projection,
+    View
 };
 
+use mesh::Mesh;
+
+
+/// Openings closer to the eye than this are looked through without narrowing the view
+const OPENING_MARGIN: f64 = 0.05;
+
+
 #[derive(Copy, Clone)]
 #[derive(Debug)]
 pub struct BoundingBox {
@@ -35,7 +43,6 @@ impl BoundingBox {
     }
 
 
-    #[allow(dead_code)]
     pub fn center(&self) -> Vector3 {
         0.5 * (self.min + self.max)
     }
@@ -160,61 +167,163 @@ impl BoundingBox {
             None
         }
     }
+
+
+    /// The corners of the box, numbered by which of x, y and z are at the maximum in bits 0, 1 and 2
+    pub fn corners(&self) -> [Vector3; 8] {
+        let mut corners = [self.min; 8];
+
+        for (i, corner) in corners.iter_mut().enumerate() {
+            if i & 1 != 0 { corner.x = self.max.x }
+            if i & 2 != 0 { corner.y = self.max.y }
+            if i & 4 != 0 { corner.z = self.max.z }
+        }
+
+        corners
+    }
 }
 
 
 impl Draw for BoundingBox {
     fn draw(&self) -> DrawCommand {
-        let lx = self.min.x as f32;
-        let gx = self.max.x as f32;
-        let ly = self.min.y as f32;
-        let gy = self.max.y as f32;
-        let lz = self.min.z as f32;
-        let gz = self.max.z as f32;
-
-        DrawCommand::IndexedVertices {
-            vertices: vec![
-                Vertex {
-                    position: [lx, ly, lz],
-                    color: if let Some(color) = self.color { color.into() } else { [0.0, 0.0, 0.0, 1.0] }
-                },
-                Vertex {
-                    position: [gx, ly, lz],
-                    color: if let Some(color) = self.color { color.into() } else { [1.0, 0.0, 0.0, 1.0] }
-                },
-                Vertex {
-                    position: [lx, gy, lz],
-                    color: if let Some(color) = self.color { color.into() } else { [0.0, 1.0, 0.0, 1.0] }
-                },
-                Vertex {
-                    position: [gx, gy, lz],
-                    color: if let Some(color) = self.color { color.into() } else { [1.0, 1.0, 0.0, 1.0] }
-                },
-
-                Vertex {
-                    position: [lx, ly, gz],
-                    color: if let Some(color) = self.color { color.into() } else { [0.0, 0.0, 1.0, 1.0] }
-                },
-                Vertex {
-                    position: [gx, ly, gz],
-                    color: if let Some(color) = self.color { color.into() } else { [1.0, 0.0, 1.0, 1.0] }
-                },
-                Vertex {
-                    position: [lx, gy, gz],
-                    color: if let Some(color) = self.color { color.into() } else { [0.0, 1.0, 1.0, 1.0] }
-                },
-                Vertex {
-                    position: [gx, gy, gz],
-                    color: if let Some(color) = self.color { color.into() } else { [1.0, 1.0, 1.0, 1.0] }
-                },
-            ],
-            indices: vec![
-                0, 1, 2, 0, 2, 4, 0, 4, 1,
-                3, 2, 1, 3, 1, 7, 3, 7, 2,
-                5, 4, 7, 5, 7, 1, 5, 1, 4,
-                6, 7, 4, 6, 4, 2, 6, 2, 7,
+        Mesh::from_box(self).draw()
+    }
+}
+
+
+
+/// A plane dividing space, with the normal pointing to the front side
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct Plane {
+    pub normal: Vector3,
+    pub distance: f64,
+}
+
+impl Plane {
+    /// The plane through a point facing a direction
+    pub fn new(point: Vector3, normal: Vector3) -> Plane {
+        let normal = normal.normal();
+
+        Plane {
+            normal,
+            distance: -normal.dot(point),
+        }
+    }
+
+    /// Distance of a point from the plane, negative behind it
+    pub fn distance_to(&self, point: Vector3) -> f64 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+
+
+/// The volume seen through a perspective projection, bounded by planes facing inwards.
+/// Starts out with six planes, looking through openings adds more.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Frustum {
+    pub eye: Vector3,
+
+    planes: Vec<Plane>,
+}
+
+impl Frustum {
+    /// Only perspective projections looking from a point have a frustum
+    pub fn new(projection: &Projection, view: &View) -> Option<Frustum> {
+        let (fov, aspect, near, far) = match *projection {
+            Projection::Perspective { fov, aspect, near, far } => (fov, aspect, near, far),
+            _ => return None
+        };
+
+        let (eye, target, up) = match *view {
+            View::LookAt { eye, target, up } => (eye, target, up),
+            _ => return None
+        };
+
+        let forward = (target - eye).normal();
+        let right = forward.cross(up).normal();
+        let up = right.cross(forward);
+
+        // The field of view is vertical and in degrees
+        let tan_y = (0.5 * fov.to_radians()).tan();
+        let tan_x = tan_y * aspect;
+
+        Some(Frustum {
+            eye,
+
+            planes: vec![
+                Plane::new(eye + near * forward, forward),
+                Plane::new(eye + far * forward, -1.0 * forward),
+
+                Plane::new(eye, tan_x * forward - right),
+                Plane::new(eye, tan_x * forward + right),
+                Plane::new(eye, tan_y * forward - up),
+                Plane::new(eye, tan_y * forward + up),
             ]
+        })
+    }
+
+
+    /// The part of the frustum seen through a flat convex opening, given by its corners in order.
+    /// Returns None if the opening is out of view.
+    pub fn through(&self, opening: &[Vector3]) -> Option<Frustum> {
+        // Out of view if every corner is behind the same plane
+        let hidden = self.planes.iter().any(|plane| {
+            opening.iter().all(|&corner| { plane.distance_to(corner) < 0.0 })
+        });
+
+        if hidden {
+            return None;
+        }
+
+        let eye = self.eye;
+
+        let mut center = Vector3::new(0.0, 0.0, 0.0);
+        for &corner in opening.iter() {
+            center += (1.0 / opening.len() as f64) * corner;
+        }
+
+        // Looking at the opening edge on, the planes through its sides would be degenerate
+        let normal = (opening[1] - opening[0]).cross(opening[2] - opening[0]);
+        if normal.dot(eye - opening[0]).abs() < OPENING_MARGIN * normal.dot(normal).sqrt() {
+            return Some(self.clone());
+        }
+
+        let mut planes = self.planes.clone();
+
+        for (i, &a) in opening.iter().enumerate() {
+            let b = opening[(i + 1) % opening.len()];
+
+            let mut normal = (a - eye).cross(b - eye);
+            if normal.dot(center - eye) < 0.0 {
+                normal = -1.0 * normal;
+            }
+
+            planes.push(Plane::new(eye, normal));
         }
+
+        Some(Frustum {
+            eye,
+            planes,
+        })
+    }
+
+
+    /// Whether a box might be visible.
+    /// Boxes outside but near a corner of the frustum are let through, they're rare and drawing them is harmless.
+    pub fn intersects(&self, bounds: &BoundingBox) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal is the last to leave the front of the plane
+            let corner = Vector3::new(
+                if plane.normal.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+                if plane.normal.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+                if plane.normal.z >= 0.0 { bounds.max.z } else { bounds.min.z },
+            );
+
+            plane.distance_to(corner) >= 0.0
+        })
     }
 }
 
@@ -356,3 +465,54 @@ impl Rectangle {
         }
     }
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// Looking along the x axis, twice as wide as it is high, with the right side towards positive z
+    fn frustum() -> Frustum {
+        let eye = Vector3::new(0.0, 0.0, 0.0);
+
+        Frustum::new(
+            &Projection::Perspective { fov: 60.0, aspect: 2.0, near: 0.1, far: 50.0 },
+            &View::LookAt { eye, target: Vector3::new(1.0, 0.0, 0.0), up: Vector3::new(0.0, 1.0, 0.0) },
+        ).unwrap()
+    }
+
+    fn visible(x: f64, y: f64, z: f64) -> bool {
+        frustum().intersects(&BoundingBox::cube(Vector3::new(x, y, z), 0.5))
+    }
+
+
+    #[test]
+    fn box_in_front_is_visible() {
+        assert!(visible(10.0, 0.0, 0.0));
+
+        // Wider than it is high, so this is still inside the sides but would be outside the top
+        assert!(visible(10.0, 0.0, 9.0));
+        assert!(visible(10.0, 0.0, -9.0));
+    }
+
+    #[test]
+    fn box_behind_is_hidden() {
+        assert!(!visible(-10.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn boxes_past_the_sides_are_hidden() {
+        assert!(!visible(10.0, 0.0, 20.0));
+        assert!(!visible(10.0, 0.0, -20.0));
+        assert!(!visible(10.0, 8.0, 0.0));
+        assert!(!visible(10.0, -8.0, 0.0));
+    }
+
+    #[test]
+    fn box_past_the_far_plane_is_hidden() {
+        assert!(visible(45.0, 0.0, 0.0));
+        assert!(!visible(60.0, 0.0, 0.0));
+    }
+}
@@ -6,11 +6,39 @@ use graphics_3d::{
     trap::Vector3
 };
 
+use orientation::Orientation;
+
+
+/// Trauma lost per second, shaking stops a while after the last hit
+const TRAUMA_DECAY: f64 = 1.5;
+
+/// Largest rotation and offset of the camera at full trauma
+const MAX_SHAKE_ANGLE: f64 = 0.04;
+const MAX_SHAKE_OFFSET: f64 = 0.05;
+const MAX_SHAKE_ROLL: f64 = 0.03;
+
+/// Steps per second at walking speed
+const BOB_FREQUENCY: f64 = 1.8;
+
+/// How far the head moves up and down, and side to side, at walking speed
+const BOB_HEIGHT: f64 = 0.04;
+const BOB_SWAY: f64 = 0.025;
+
+/// Degrees added to the field of view while sprinting
+const FOV_KICK: f64 = 8.0;
+
+/// Seconds it takes the head-bob and field of view to mostly catch up with a change in speed
+const EASE_TIME: f64 = 0.1;
+
+#[derive(Copy, Clone)]
 pub struct Camera {
     pub position: Vector3,
 
     yaw: f64,
     pitch: f64,
+
+    /// Leaning to the right, in radians
+    roll: f64,
 }
 
 impl Camera {
@@ -19,9 +47,20 @@ impl Camera {
             position,
             yaw: 0.0,
             pitch: 0.0,
+            roll: 0.0,
         }
     }
 
+    /// Blend the position from a previous state of the camera.
+    /// The orientation follows the mouse directly, so it is kept as is.
+    pub fn interpolate(&self, previous: &Camera, alpha: f64) -> Camera {
+        Camera {
+            position: previous.position + alpha * (self.position - previous.position),
+            ..*self
+        }
+    }
+
+
     pub fn rotate(&mut self, dx: f64, dy: f64) {
         self.yaw += dx;
         self.pitch += dy;
@@ -29,11 +68,17 @@ impl Camera {
         if self.pitch > PI * 0.49 { self.pitch = PI * 0.49 }
         if self.pitch < PI * -0.49 { self.pitch = PI * -0.49 }
 
-        if self.yaw > PI * 2.0 { self.yaw -= PI * 2.0 };
-        if self.yaw < PI * -2.0 { self.yaw += PI * 2.0 };
+        // Keep the yaw within one turn either side of zero, so it doesn't lose precision
+        if self.yaw > PI { self.yaw -= PI * 2.0 };
+        if self.yaw < -PI { self.yaw += PI * 2.0 };
     }
 
 
+    pub fn orientation(&self) -> Orientation {
+        Orientation::from_euler(self.yaw, self.pitch, self.roll)
+    }
+
+    /// The forward direction of the orientation, computed directly as roll doesn't change it
     pub fn direction(&self) -> Vector3 {
         Vector3::new(
             self.pitch.cos() * self.yaw.sin(),
@@ -42,11 +87,183 @@ impl Camera {
         )
     }
 
+    /// A camera with the same orientation, backed away from a target until it is a distance behind it
+    pub fn orbit(&self, target: Vector3, distance: f64) -> Camera {
+        Camera {
+            position: target - distance * self.direction(),
+            ..*self
+        }
+    }
+
     pub fn view(&self) -> View {
-        View::LookAt {
-            eye: self.position,
-            target: (self.position + self.direction()),
-            up: Vector3::new(0.0, 1.0, 0.0),
+        self.orientation().view(self.position)
+    }
+}
+
+
+/// How the view is controlled
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraMode {
+    /// Walk with the player, held back by walls and gravity
+    Walk,
+
+    /// Walk with the player, seen from behind
+    ThirdPerson,
+
+    /// Walk with the player, seen from a view that turns around them independently
+    Orbit,
+
+    /// Fly with the player through walls, along the full view direction
+    Noclip,
+
+    /// Fly a detached camera, leaving the player where they are
+    Spectator,
+}
+
+
+impl CameraMode {
+    pub fn from_name(name: &str) -> Option<CameraMode> {
+        Some(match name {
+            "walk" => CameraMode::Walk,
+            "thirdperson" => CameraMode::ThirdPerson,
+            "orbit" => CameraMode::Orbit,
+            "noclip" => CameraMode::Noclip,
+            "spectator" => CameraMode::Spectator,
+
+            _ => return None
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CameraMode::Walk => "walk",
+            CameraMode::ThirdPerson => "thirdperson",
+            CameraMode::Orbit => "orbit",
+            CameraMode::Noclip => "noclip",
+            CameraMode::Spectator => "spectator",
+        }
+    }
+
+    /// The mode after this one, for cycling through them with a key
+    pub fn next(&self) -> CameraMode {
+        match *self {
+            CameraMode::Walk => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Noclip,
+            CameraMode::Noclip => CameraMode::Spectator,
+            CameraMode::Spectator => CameraMode::Walk,
+        }
+    }
+
+    /// Whether the player passes through walls and ignores gravity
+    pub fn flying(&self) -> bool {
+        *self == CameraMode::Noclip
+    }
+
+    /// Whether the player is moved with gravity and collisions
+    pub fn walking(&self) -> bool {
+        matches!(*self, CameraMode::Walk | CameraMode::ThirdPerson | CameraMode::Orbit)
+    }
+
+    /// Whether the view is turned separately from the player
+    pub fn detached(&self) -> bool {
+        matches!(*self, CameraMode::Orbit | CameraMode::Spectator)
+    }
+
+    /// Whether the view is from the player's eyes
+    pub fn first_person(&self) -> bool {
+        matches!(*self, CameraMode::Walk | CameraMode::Noclip)
+    }
+}
+
+
+
+/// Movement of the view that doesn't change where the player is or looks
+pub struct CameraEffects {
+    /// From 0 to 1, the camera shakes with the square of this
+    trauma: f64,
+    time: f64,
+
+    bob_phase: f64,
+    /// How strongly the head bobs, follows the walking speed
+    bob_weight: f64,
+
+    fov_kick: f64,
+}
+
+
+impl CameraEffects {
+    pub fn new() -> CameraEffects {
+        CameraEffects {
+            trauma: 0.0,
+            time: 0.0,
+
+            bob_phase: 0.0,
+            bob_weight: 0.0,
+
+            fov_kick: 0.0,
         }
     }
-}
\ No newline at end of file
+
+
+    pub fn add_trauma(&mut self, amount: f64) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    /// Advance the effects, `walk_speed` is the speed along the ground relative to walking
+    pub fn update(&mut self, dt: f64, walk_speed: f64, sprinting: bool) {
+        self.time += dt;
+        self.trauma = (self.trauma - TRAUMA_DECAY * dt).max(0.0);
+
+        let ease = 1.0 - (-dt / EASE_TIME).exp();
+
+        let walk_speed = walk_speed.min(2.0);
+        self.bob_weight += ease * (walk_speed.min(1.0) - self.bob_weight);
+        self.bob_phase = (self.bob_phase + dt * BOB_FREQUENCY * walk_speed * PI) % (2.0 * PI);
+
+        let kick = if sprinting { FOV_KICK } else { 0.0 };
+        self.fov_kick += ease * (kick - self.fov_kick);
+    }
+
+
+    /// Degrees to add to the field of view
+    pub fn fov_kick(&self) -> f64 {
+        self.fov_kick
+    }
+
+    /// The camera moved by the shake and head-bob
+    pub fn apply(&self, camera: &Camera) -> Camera {
+        let shake = self.trauma * self.trauma;
+
+        // Smooth pseudo-random noise, different for every axis
+        let noise = |axis: f64| {
+            let t = self.time * 23.0 + axis * 17.3;
+            0.6 * t.sin() + 0.4 * (2.7 * t + axis).sin()
+        };
+
+        let right = camera.orientation().right();
+
+        // The head dips twice per stride and sways once
+        let bob_height = BOB_HEIGHT * self.bob_weight * (2.0 * self.bob_phase).sin();
+        let bob_sway = BOB_SWAY * self.bob_weight * self.bob_phase.sin();
+
+        let offset = Vector3::new(
+            MAX_SHAKE_OFFSET * shake * noise(0.0),
+            MAX_SHAKE_OFFSET * shake * noise(1.0) + bob_height,
+            MAX_SHAKE_OFFSET * shake * noise(2.0),
+        );
+
+        let mut position = camera.position;
+        position += offset;
+        position += bob_sway * right;
+
+        let pitch = camera.pitch + MAX_SHAKE_ANGLE * shake * noise(3.0);
+
+        Camera {
+            position,
+            yaw: camera.yaw + MAX_SHAKE_ANGLE * shake * noise(4.0),
+            pitch: pitch.clamp(PI * -0.49, PI * 0.49),
+            roll: camera.roll + MAX_SHAKE_ROLL * shake * noise(5.0),
+        }
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    fs::File,
+    io::{
+        Read,
+        Write,
+    },
+};
+
+use graphics_3d::trap::{
+    Vector2,
+    Vector3,
+};
+
+use camera::CameraMode;
+
+use lighting::FogMode;
+
+use super::{
+    GameState,
+
+    physics::PropKind,
+    enemies::Enemy,
+    states::{
+        SENSITIVITY_RANGE,
+        DRAW_DISTANCE_RANGE,
+    },
+    pads::{
+        Pad,
+        PadKind,
+    },
+    items::{
+        ItemKind,
+        Pickup,
+    },
+    weapons::WEAPONS,
+    world::{
+        World,
+        WorldSnapshot,
+    },
+};
+
+
+/// Lines of output kept in the console
+const LOG_LENGTH: usize = 64;
+
+/// Time step used to advance the game when a script waits
+const WAIT_STEP: f64 = 1.0 / 60.0;
+
+/// Longest a single `wait` advances the game, in seconds
+const MAX_WAIT: f64 = 600.0;
+
+/// Scripts can run other scripts, but not deeper than this so a script that runs itself stops
+const MAX_SCRIPT_DEPTH: usize = 8;
+
+/// Fastest the simulation can be set to step, in steps per second
+const MAX_TICKRATE: f64 = 1000.0;
+
+/// How far in front of the player things are spawned
+const SPAWN_DISTANCE: f64 = 3.0;
+
+/// Frames timed by `bench` when no count is given
+const BENCH_FRAMES: usize = 100;
+
+
+
+/// The output of the console and the commands entered into it
+pub struct Console {
+    log: Vec<String>,
+    history: Vec<String>,
+
+    /// Scripts being run, each one started by the one before
+    scripts: usize,
+}
+
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            log: Vec::new(),
+            history: Vec::new(),
+
+            scripts: 0,
+        }
+    }
+
+
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+
+    /// Previously entered commands, oldest first
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+
+    pub fn print(&mut self, text: &str) {
+        self.log.extend(text.lines().map(String::from));
+
+        if self.log.len() > LOG_LENGTH {
+            let excess = self.log.len() - LOG_LENGTH;
+            self.log.drain(..excess);
+        }
+    }
+
+    pub fn remember(&mut self, line: &str) {
+        if self.history.last().is_none_or(|last| { last != line }) {
+            self.history.push(line.to_string());
+        }
+    }
+}
+
+
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+
+    run: fn(&mut GameState, &[&str]) -> Result<String, String>,
+}
+
+
+/// Every command the console understands
+static COMMANDS: [Command; 17] = [
+    Command { name: "help", usage: "help", help: "list all commands", run: help },
+    Command { name: "teleport", usage: "teleport <x> <y> <z>", help: "move the player", run: teleport },
+    Command { name: "seed", usage: "seed", help: "show the seed the world was generated from", run: seed },
+    Command { name: "regen", usage: "regen [seed]", help: "generate a new world", run: regen },
+    Command { name: "save", usage: "save <file>", help: "save the rooms explored so far", run: save },
+    Command { name: "load", usage: "load <file>", help: "load saved rooms, starting again in the first room", run: load },
+    Command { name: "noclip", usage: "noclip", help: "toggle flying through walls", run: noclip },
+    Command { name: "camera", usage: "camera [walk|thirdperson|orbit|noclip|spectator]", help: "show or change the camera mode", run: camera },
+    Command { name: "give", usage: "give <item>", help: "give health, ammo, key, armour or a weapon by name", run: give },
+    Command { name: "spawn", usage: "spawn <thing>", help: "spawn a crate, barrel, enemy, spikes, checkpoint or item in front of the player", run: spawn },
+    Command { name: "timescale", usage: "timescale <scale>", help: "speed up or slow down the simulation", run: timescale },
+    Command { name: "size", usage: "size <size>", help: "resize the player", run: size },
+    Command { name: "set", usage: "set [variable] [value]", help: "show or change variables", run: set },
+    Command { name: "exec", usage: "exec <file>", help: "run the commands in a file", run: exec },
+    Command { name: "bench", usage: "bench [frames]", help: "time building the world geometry box by box as before the meshes, and from the cached meshes", run: bench },
+    Command { name: "wait", usage: "wait <seconds>", help: "advance the game, for use in scripts", run: wait },
+    Command { name: "clear", usage: "clear", help: "clear the console", run: clear },
+];
+
+
+/// Variables that can be read and changed with `set`
+static VARIABLES: [(&str, &str); 16] = [
+    ("fov", "vertical field of view in degrees"),
+    ("sensitivity", "radians turned per pixel of mouse movement"),
+    ("invert_y", "1 to look down when the mouse moves up"),
+    ("smoothing", "1 to smooth mouse movement"),
+    ("draw_distance", "distance to the far plane, nothing further away is drawn"),
+    ("fog", "0 for no fog, 1 for linear fog, 2 for exponential fog"),
+    ("fog_start", "distance where linear fog starts"),
+    ("size", "size of the player"),
+    ("camera_distance", "distance from the player to the camera in third person"),
+    ("timescale", "speed of the simulation"),
+    ("tickrate", "simulation steps per second"),
+    ("map_scale", "pixels per unit on the full screen map"),
+    ("crosshair_size", "length of the crosshair in pixels"),
+    ("crosshair_width", "thickness of the crosshair in pixels"),
+    ("health", "health of the player"),
+    ("armour", "armour of the player"),
+];
+
+
+
+/// Run a line of input, returning the output of the command
+pub fn execute(game: &mut GameState, line: &str) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return Ok(String::new()),
+    };
+
+    let command = COMMANDS.iter()
+        .find(|command| { command.name == name })
+        .ok_or_else(|| { format!("unknown command `{}`, try `help`", name) })?;
+
+    (command.run)(game, args)
+}
+
+
+/// Run every line of a script, stopping at the first error.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn run_script(game: &mut GameState, path: &str) -> Result<String, String> {
+    if game.console.scripts >= MAX_SCRIPT_DEPTH {
+        return Err(format!("{}: scripts can only run {} deep", path, MAX_SCRIPT_DEPTH));
+    }
+
+    game.console.scripts += 1;
+    let result = run_lines(game, path);
+    game.console.scripts -= 1;
+
+    result
+}
+
+fn run_lines(game: &mut GameState, path: &str) -> Result<String, String> {
+    let mut source = String::new();
+
+    File::open(path)
+        .and_then(|mut file| { file.read_to_string(&mut source) })
+        .map_err(|e| { format!("{}: {}", path, e) })?;
+
+    let mut output = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = execute(game, line).map_err(|e| { format!("{}: line {}: {}", path, number + 1, e) })?;
+
+        if !result.is_empty() {
+            output.push(result);
+        }
+    }
+
+    Ok(output.join("\n"))
+}
+
+
+
+fn parse<T: ::std::str::FromStr>(args: &[&str], index: usize, usage: &str) -> Result<T, String> {
+    args.get(index)
+        .and_then(|arg| { arg.parse().ok() })
+        .ok_or_else(|| { format!("usage: {}", usage) })
+}
+
+
+fn help(_game: &mut GameState, _args: &[&str]) -> Result<String, String> {
+    let lines: Vec<String> = COMMANDS.iter()
+        .map(|command| { format!("{} - {}", command.usage, command.help) })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
+
+
+fn teleport(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let usage = "teleport <x> <y> <z>";
+    let position = Vector3::new(parse(args, 0, usage)?, parse(args, 1, usage)?, parse(args, 2, usage)?);
+
+    game.teleport(position);
+
+    Ok(String::new())
+}
+
+
+fn seed(game: &mut GameState, _args: &[&str]) -> Result<String, String> {
+    Ok(format!("{}", game.seed))
+}
+
+
+fn regen(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    use rand::Rng;
+
+    // Drawn from the game's own generator so replays pick the same world
+    let seed = if args.is_empty() {
+        game.rng.gen()
+    } else {
+        parse(args, 0, "regen [seed]")?
+    };
+
+    game.regenerate(seed);
+
+    Ok(format!("generated world {}", seed))
+}
+
+
+fn save(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| { "usage: save <file>".to_string() })?;
+
+    File::create(path)
+        .and_then(|mut file| { write!(file, "{}", game.world.snapshot()) })
+        .map_err(|e| { format!("{}: {}", path, e) })?;
+
+    Ok(format!("saved {} rooms to {}", game.world.room_count(), path))
+}
+
+
+fn load(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| { "usage: load <file>".to_string() })?;
+
+    let snapshot = WorldSnapshot::load(path)?;
+    let world = World::restore(&snapshot).map_err(|e| { format!("{}: {}", path, e) })?;
+
+    game.replace_world(world);
+
+    Ok(format!("loaded {} rooms from {}", game.world.room_count(), path))
+}
+
+
+fn noclip(game: &mut GameState, _args: &[&str]) -> Result<String, String> {
+    let mode = if game.camera_mode == CameraMode::Noclip { CameraMode::Walk } else { CameraMode::Noclip };
+    game.set_camera_mode(mode);
+
+    Ok(format!("camera {}", mode.name()))
+}
+
+
+fn camera(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    if let Some(name) = args.first() {
+        let mode = CameraMode::from_name(name).ok_or_else(|| { format!("unknown camera mode `{}`", name) })?;
+        game.set_camera_mode(mode);
+    }
+
+    Ok(format!("camera {}", game.camera_mode.name()))
+}
+
+
+fn give(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let name = args.first().ok_or_else(|| { "usage: give <item>".to_string() })?;
+
+    let given = match *name {
+        "health" => game.inventory.add(ItemKind::HealthPack),
+        "ammo" => game.inventory.add(ItemKind::Ammo),
+        "key" => game.inventory.add(ItemKind::Key),
+        "armour" => game.health.add_armour(game.health.max_armour),
+
+        _ => {
+            let weapon = WEAPONS.iter()
+                .position(|weapon| { weapon.name.eq_ignore_ascii_case(name) })
+                .ok_or_else(|| { format!("unknown item `{}`", name) })?;
+
+            game.arsenal.give(weapon)
+        }
+    };
+
+    if given {
+        Ok(format!("gave {}", name))
+    } else {
+        Err(format!("could not give {}", name))
+    }
+}
+
+
+fn spawn(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let name = args.first().ok_or_else(|| { "usage: spawn <thing>".to_string() })?;
+
+    let direction = game.camera.direction();
+    let forward = Vector3::new(direction.x, 0.0, direction.z).normal();
+    let mut position = game.camera.position;
+    position += SPAWN_DISTANCE * forward;
+
+    match *name {
+        "crate" => game.physics.spawn(PropKind::Crate, position),
+        "barrel" => game.physics.spawn(PropKind::Barrel, position),
+
+        "enemy" => game.enemies.push(Enemy::new(Vector3::new(position.x, 0.0, position.z))),
+
+        "spikes" => game.pads.push(Pad::new(PadKind::Spikes, Vector2::new(position.x, position.z))),
+        "checkpoint" => game.pads.push(Pad::new(PadKind::Checkpoint, Vector2::new(position.x, position.z))),
+
+        "health" => game.pickups.push(Pickup::new(ItemKind::HealthPack, position)),
+        "ammo" => game.pickups.push(Pickup::new(ItemKind::Ammo, position)),
+        "key" => game.pickups.push(Pickup::new(ItemKind::Key, position)),
+
+        _ => return Err(format!("unknown thing `{}`", name)),
+    }
+
+    Ok(format!("spawned {}", name))
+}
+
+
+fn timescale(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let scale = parse(args, 0, "timescale <scale>")?;
+    set_variable(game, "timescale", scale)
+}
+
+
+fn size(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let size = parse(args, 0, "size <size>")?;
+    set_variable(game, "size", size)
+}
+
+
+fn set(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    match args.len() {
+        0 => {
+            let lines: Vec<String> = VARIABLES.iter()
+                .map(|&(name, help)| { format!("{} = {} - {}", name, get_variable(game, name).unwrap_or(0.0), help) })
+                .collect();
+
+            Ok(lines.join("\n"))
+        }
+
+        1 => {
+            get_variable(game, args[0])
+                .map(|value| { format!("{} = {}", args[0], value) })
+                .ok_or_else(|| { format!("unknown variable `{}`", args[0]) })
+        }
+
+        _ => set_variable(game, args[0], parse(args, 1, "set [variable] [value]")?),
+    }
+}
+
+
+fn bench(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let frames = if args.is_empty() { BENCH_FRAMES } else { parse(args, 0, "bench [frames]")? };
+
+    let (before, after) = game.world.benchmark(frames, &game.lighting);
+    let (before_triangles, after_triangles) = game.world.triangle_counts();
+
+    Ok(format!(
+        "{} rooms over {} frames\nbox by box: {:.3} ms, {} triangles\ncached meshes: {:.3} ms, {} triangles",
+        game.world.visible_rooms().count(), frames,
+        1000.0 * before, before_triangles,
+        1000.0 * after, after_triangles,
+    ))
+}
+
+
+fn exec(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| { "usage: exec <file>".to_string() })?;
+    run_script(game, path)
+}
+
+
+fn wait(game: &mut GameState, args: &[&str]) -> Result<String, String> {
+    let seconds: f64 = parse(args, 0, "wait <seconds>")?;
+
+    if !seconds.is_finite() {
+        return Err("seconds must be a number".to_string());
+    }
+
+    let mut remaining = seconds.min(MAX_WAIT);
+
+    while remaining > 0.0 {
+        let dt = remaining.min(WAIT_STEP);
+        game.simulate(dt);
+        remaining -= dt;
+    }
+
+    Ok(String::new())
+}
+
+
+fn clear(game: &mut GameState, _args: &[&str]) -> Result<String, String> {
+    game.console.log.clear();
+    Ok(String::new())
+}
+
+
+
+fn get_variable(game: &GameState, name: &str) -> Option<f64> {
+    Some(match name {
+        "fov" => game.field_of_view,
+        "sensitivity" => game.mouse_sensitivity,
+        "invert_y" => if game.invert_y { 1.0 } else { 0.0 },
+        "smoothing" => if game.mouse_smoothing { 1.0 } else { 0.0 },
+        "draw_distance" => game.draw_distance,
+        "fog" => game.lighting.fog.mode.index() as f64,
+        "fog_start" => game.fog_start(),
+        "size" => game.size,
+        "camera_distance" => game.boom_length,
+        "timescale" => game.scheduler.time_scale,
+        "tickrate" => game.scheduler.rate(),
+        "map_scale" => game.map_scale,
+        "crosshair_size" => game.crosshair.size,
+        "crosshair_width" => game.crosshair.width,
+        "health" => game.health.health,
+        "armour" => game.health.armour,
+
+        _ => return None
+    })
+}
+
+
+fn set_variable(game: &mut GameState, name: &str, value: f64) -> Result<String, String> {
+    if !value.is_finite() {
+        return Err(format!("{} must be a number", name));
+    }
+
+    let positive = || {
+        if value > 0.0 { Ok(value) } else { Err(format!("{} must be positive", name)) }
+    };
+
+    match name {
+        "fov" => game.set_field_of_view(value.clamp(1.0, 179.0)),
+        "sensitivity" => {
+            let (min, max) = SENSITIVITY_RANGE;
+            game.set_mouse_sensitivity(value.clamp(min, max));
+        }
+        "invert_y" => game.set_invert_y(value != 0.0),
+        "smoothing" => game.set_mouse_smoothing(value != 0.0),
+        "draw_distance" => {
+            let (min, max) = DRAW_DISTANCE_RANGE;
+            game.set_draw_distance(value.clamp(min, max));
+        }
+        "fog" => {
+            let mode = FogMode::from_index(value as usize).ok_or_else(|| { "fog must be 0, 1 or 2".to_string() })?;
+            game.set_fog_mode(mode);
+        }
+        "fog_start" => game.set_fog_start(value),
+        "size" => game.size = value.clamp(0.1, 4.0),
+        "camera_distance" => game.boom_length = value.max(0.0),
+        "timescale" => game.scheduler.time_scale = value.max(0.0),
+        "tickrate" => game.scheduler.set_rate(positive()?.min(MAX_TICKRATE)),
+        "map_scale" => game.map_scale = positive()?,
+        "crosshair_size" => game.crosshair.size = value.max(0.0),
+        "crosshair_width" => game.crosshair.width = value.max(0.0),
+        "health" => game.health.health = value.clamp(0.0, game.health.max_health),
+        "armour" => game.health.armour = value.clamp(0.0, game.health.max_armour),
+
+        _ => return Err(format!("unknown variable `{}`", name)),
+    }
+
+    Ok(format!("{} = {}", name, get_variable(game, name).unwrap_or(value)))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+
+    #[test]
+    fn unknown_command() {
+        let mut game = GameState::with_seed(1);
+        assert_eq!(execute(&mut game, "fly 10").err().unwrap(), "unknown command `fly`, try `help`");
+    }
+
+    #[test]
+    fn wrong_argument_count() {
+        let mut game = GameState::with_seed(1);
+
+        assert_eq!(execute(&mut game, "teleport 1 2").err().unwrap(), "usage: teleport <x> <y> <z>");
+        assert_eq!(execute(&mut game, "wait").err().unwrap(), "usage: wait <seconds>");
+    }
+
+    #[test]
+    fn set_and_get_variables() {
+        let mut game = GameState::with_seed(1);
+
+        assert_eq!(execute(&mut game, "set timescale 0.5").unwrap(), "timescale = 0.5");
+        assert_eq!(execute(&mut game, "set timescale").unwrap(), "timescale = 0.5");
+
+        assert_eq!(execute(&mut game, "set gravity 2").err().unwrap(), "unknown variable `gravity`");
+        assert_eq!(execute(&mut game, "set tickrate -1").err().unwrap(), "tickrate must be positive");
+        assert!(execute(&mut game, "set timescale fast").is_err());
+    }
+
+    #[test]
+    fn tickrate_is_capped() {
+        let mut game = GameState::with_seed(1);
+
+        execute(&mut game, "set tickrate 1000000").unwrap();
+        assert_eq!(game.scheduler.rate(), MAX_TICKRATE);
+    }
+
+    #[test]
+    fn draw_distance_stays_in_range() {
+        let mut game = GameState::with_seed(1);
+        let (min, max) = DRAW_DISTANCE_RANGE;
+
+        execute(&mut game, "set draw_distance 0.001").unwrap();
+        assert_eq!(game.draw_distance(), min);
+
+        execute(&mut game, "set draw_distance 1000000").unwrap();
+        assert_eq!(game.draw_distance(), max);
+
+        assert!(execute(&mut game, "set draw_distance inf").is_err());
+        assert_eq!(game.draw_distance(), max);
+    }
+
+    #[test]
+    fn sensitivity_stays_in_range() {
+        let mut game = GameState::with_seed(1);
+
+        for value in ["nan", "inf", "-1"].iter() {
+            execute(&mut game, &format!("set sensitivity {}", value)).ok();
+
+            let (min, max) = SENSITIVITY_RANGE;
+            assert!(game.mouse_sensitivity() >= min && game.mouse_sensitivity() <= max);
+        }
+    }
+
+    #[test]
+    fn wait_rejects_non_finite_seconds() {
+        let mut game = GameState::with_seed(1);
+
+        assert!(execute(&mut game, "wait inf").is_err());
+        assert!(execute(&mut game, "wait NaN").is_err());
+    }
+
+    #[test]
+    fn script_that_runs_itself_stops() {
+        let path = env::temp_dir().join("rogue_console_recursive.cfg");
+        let path = path.to_str().unwrap();
+
+        File::create(path)
+            .and_then(|mut file| { write!(file, "exec {}", path) })
+            .unwrap();
+
+        let mut game = GameState::with_seed(1);
+        let error = run_script(&mut game, path).err().unwrap();
+
+        assert!(error.ends_with(&format!("scripts can only run {} deep", MAX_SCRIPT_DEPTH)));
+        assert_eq!(game.console.scripts, 0);
+    }
+}
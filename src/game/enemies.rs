@@ -0,0 +1,147 @@
+use graphics_3d::{
+    trap::Vector3,
+    Color
+};
+
+use bounding_box::BoundingBox;
+
+
+/// Damage dealt by each attack
+pub const ENEMY_DAMAGE: f64 = 15.0;
+
+/// How hard an enemy pushes props out of its way
+pub const ENEMY_MASS: f64 = 60.0;
+
+const ENEMY_HEALTH: f64 = 60.0;
+
+/// Units per second an enemy walks towards the player
+const CHASE_SPEED: f64 = 2.5;
+
+/// Enemies only notice the player closer than this
+const SIGHT_RANGE: f64 = 14.0;
+
+/// Distance between the edges of the two hulls at which an enemy can hit the player
+const ATTACK_RANGE: f64 = 0.3;
+
+/// Seconds between attacks
+const ATTACK_INTERVAL: f64 = 1.0;
+
+const HALF_WIDTH: f64 = 0.35;
+const HEIGHT: f64 = 1.4;
+
+/// Seconds an enemy flashes after being hurt
+const HURT_TIME: f64 = 0.1;
+
+
+/// A hostile creature that walks up to the player and hits them
+pub struct Enemy {
+    /// The middle of its feet
+    pub position: Vector3,
+    pub previous_position: Vector3,
+
+    pub health: f64,
+
+    /// Seconds until it can attack again
+    cooldown: f64,
+
+    hurt: f64,
+}
+
+
+impl Enemy {
+    pub fn new(position: Vector3) -> Enemy {
+        Enemy {
+            position,
+            previous_position: position,
+
+            health: ENEMY_HEALTH,
+
+            cooldown: 0.0,
+
+            hurt: 0.0,
+        }
+    }
+
+
+    pub fn hull(&self) -> BoundingBox {
+        self.hull_at(self.position)
+    }
+
+    fn hull_at(&self, position: Vector3) -> BoundingBox {
+        BoundingBox {
+            min: position - Vector3::new(HALF_WIDTH, 0.0, HALF_WIDTH),
+            max: position + Vector3::new(HALF_WIDTH, HEIGHT, HALF_WIDTH),
+
+            color: Some(if self.hurt > 0.0 {
+                Color::new(1.0, 1.0, 1.0, 1.0)
+            } else {
+                Color::new(0.45, 0.1, 0.5, 1.0)
+            }),
+        }
+    }
+
+    /// The hull blended from the last step
+    pub fn interpolate(&self, alpha: f64) -> BoundingBox {
+        self.hull_at(self.previous_position + alpha * (self.position - self.previous_position))
+    }
+
+    /// Where the enemy looks from
+    pub fn eye(&self) -> Vector3 {
+        self.position + Vector3::new(0.0, 0.8 * HEIGHT, 0.0)
+    }
+
+
+    /// Whether the player is close enough to be noticed
+    pub fn in_range(&self, target: Vector3) -> bool {
+        let offset = target - self.eye();
+        offset.dot(offset) < SIGHT_RANGE * SIGHT_RANGE
+    }
+
+
+    /// Walk towards the player's hull if it can be seen.
+    /// Returns the damage dealt to the player this step.
+    pub fn update(&mut self, dt: f64, target: &BoundingBox, seen: bool) -> f64 {
+        self.previous_position = self.position;
+        self.cooldown = (self.cooldown - dt).max(0.0);
+        self.hurt = (self.hurt - dt).max(0.0);
+
+        if !seen {
+            return 0.0;
+        }
+
+        let center = target.center();
+        let offset = Vector3::new(center.x - self.position.x, 0.0, center.z - self.position.z);
+        let distance = offset.dot(offset).sqrt();
+
+        // Distance between the sides of the hulls facing each other
+        let gap = distance - HALF_WIDTH - 0.5 * target.size().x;
+
+        if gap > ATTACK_RANGE {
+            let step = (CHASE_SPEED * dt).min(gap);
+            self.position += (step / distance) * offset;
+            0.0
+        } else if self.cooldown <= 0.0 && target.min.y < self.position.y + HEIGHT {
+            self.cooldown = ATTACK_INTERVAL;
+            ENEMY_DAMAGE
+        } else {
+            0.0
+        }
+    }
+
+
+    /// Move out of something it walked into. Enemies stay on the floor, so they are only pushed sideways.
+    pub fn push_out(&mut self, resolve: Vector3) {
+        self.position.x += resolve.x;
+        self.position.z += resolve.z;
+    }
+
+
+    /// Take damage, returns true if it died
+    pub fn damage(&mut self, amount: f64) -> bool {
+        self.health -= amount;
+        self.hurt = HURT_TIME;
+
+        self.health <= 0.0
+    }
+}
+
@@ -0,0 +1,72 @@
+
+
+/// Fraction of incoming damage absorbed by armour while it lasts
+const ARMOUR_ABSORPTION: f64 = 2.0 / 3.0;
+
+
+pub struct Health {
+    pub health: f64,
+    pub max_health: f64,
+
+    pub armour: f64,
+    pub max_armour: f64,
+}
+
+
+impl Health {
+    pub fn new(max_health: f64, max_armour: f64) -> Health {
+        Health {
+            health: max_health,
+            max_health,
+
+            armour: 0.0,
+            max_armour,
+        }
+    }
+
+
+    pub fn dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+
+    /// Take damage, part of which is absorbed by the armour
+    pub fn damage(&mut self, amount: f64) {
+        let absorbed = (ARMOUR_ABSORPTION * amount).min(self.armour);
+
+        self.armour -= absorbed;
+        self.health -= amount - absorbed;
+
+        if self.health < 0.0 {
+            self.health = 0.0;
+        }
+    }
+
+
+    /// Restore health, returns false if already at full health
+    pub fn heal(&mut self, amount: f64) -> bool {
+        if self.health >= self.max_health {
+            return false;
+        }
+
+        self.health = (self.health + amount).min(self.max_health);
+        true
+    }
+
+    /// Add armour, returns false if already at full armour
+    pub fn add_armour(&mut self, amount: f64) -> bool {
+        if self.armour >= self.max_armour {
+            return false;
+        }
+
+        self.armour = (self.armour + amount).min(self.max_armour);
+        true
+    }
+
+
+    /// Restore full health and remove all armour
+    pub fn reset(&mut self) {
+        self.health = self.max_health;
+        self.armour = 0.0;
+    }
+}
@@ -0,0 +1,312 @@
+use graphics_3d::{
+    Draw,
+    DrawCommand,
+    Vertex,
+};
+
+use super::items::ItemKind;
+
+use super::text::{
+    push_text,
+    text_width,
+    GLYPH_HEIGHT,
+};
+
+
+/// Append an axis-aligned rectangle in screen space
+pub fn push_quad(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>,
+                 min: (f64, f64), max: (f64, f64), color: [f32; 4]) {
+    let i = vertices.len() as u32;
+
+    vertices.push(Vertex::new([min.0 as f32, min.1 as f32, 0.0]).with_color(color));
+    vertices.push(Vertex::new([max.0 as f32, min.1 as f32, 0.0]).with_color(color));
+    vertices.push(Vertex::new([max.0 as f32, max.1 as f32, 0.0]).with_color(color));
+    vertices.push(Vertex::new([min.0 as f32, max.1 as f32, 0.0]).with_color(color));
+
+    indices.extend_from_slice(&[i, i + 1, i + 2, i + 2, i + 3, i]);
+}
+
+
+/// Append a hollow rectangle whose border lies inside the given bounds
+pub fn push_frame(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>,
+                  min: (f64, f64), max: (f64, f64), thickness: f64, color: [f32; 4]) {
+    let t = thickness;
+
+    push_quad(vertices, indices, min, (max.0, min.1 + t), color);
+    push_quad(vertices, indices, (min.0, max.1 - t), max, color);
+    push_quad(vertices, indices, (min.0, min.1 + t), (min.0 + t, max.1 - t), color);
+    push_quad(vertices, indices, (max.0 - t, min.1 + t), (max.0, max.1 - t), color);
+}
+
+
+
+/// A row of pips showing the rounds left in the magazine, anchored at its bottom right corner
+pub struct AmmoCounter {
+    pub x: f64,
+    pub y: f64,
+
+    pub ammo: u32,
+    pub magazine: u32,
+    pub reloading: bool,
+}
+
+
+impl Draw for AmmoCounter {
+    fn draw(&self) -> DrawCommand {
+        const LOADED: [f32; 4] = [0.8, 0.7, 0.3, 1.0];
+        const EMPTY: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+        const RELOADING: [f32; 4] = [0.4, 0.2, 0.1, 1.0];
+
+        let width = 6.0;
+        let height = 16.0;
+        let gap = 3.0;
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for i in 0..self.magazine {
+            let right = self.x - (self.magazine - 1 - i) as f64 * (width + gap);
+
+            let color = if self.reloading {
+                RELOADING
+            } else if i < self.ammo {
+                LOADED
+            } else {
+                EMPTY
+            };
+
+            push_quad(&mut vertices, &mut indices,
+                      (right - width, self.y - height), (right, self.y), color);
+        }
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// A horizontal bar filled from the left, anchored at its bottom left corner
+pub struct Bar {
+    pub x: f64,
+    pub y: f64,
+
+    pub width: f64,
+    pub height: f64,
+
+    /// How much of the bar is filled, between 0 and 1
+    pub fill: f64,
+    pub color: [f32; 4],
+}
+
+
+impl Draw for Bar {
+    fn draw(&self) -> DrawCommand {
+        const BACKGROUND: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+
+        let fill = self.fill.clamp(0.0, 1.0);
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        push_quad(&mut vertices, &mut indices,
+                  (self.x + fill * self.width, self.y - self.height), (self.x + self.width, self.y), BACKGROUND);
+
+        if fill > 0.0 {
+            push_quad(&mut vertices, &mut indices,
+                      (self.x, self.y - self.height), (self.x + fill * self.width, self.y), self.color);
+        }
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// A single colour covering the whole screen
+pub struct Overlay {
+    pub width: f64,
+    pub height: f64,
+
+    pub color: [f32; 4],
+}
+
+
+impl Draw for Overlay {
+    fn draw(&self) -> DrawCommand {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        push_quad(&mut vertices, &mut indices, (0.0, 0.0), (self.width, self.height), self.color);
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// A filled rectangle with a border inside it
+pub struct Panel {
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+
+    pub color: [f32; 4],
+    pub border: [f32; 4],
+}
+
+
+impl Draw for Panel {
+    fn draw(&self) -> DrawCommand {
+        let thickness = 2.0;
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        push_frame(&mut vertices, &mut indices, self.min, self.max, thickness, self.border);
+        push_quad(&mut vertices, &mut indices,
+                  (self.min.0 + thickness, self.min.1 + thickness),
+                  (self.max.0 - thickness, self.max.1 - thickness),
+                  self.color);
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// A row of inventory slots, anchored at its bottom center
+pub struct InventoryPanel<'a> {
+    pub x: f64,
+    pub y: f64,
+
+    pub items: &'a [ItemKind],
+    pub capacity: usize,
+    pub selected: usize,
+}
+
+
+impl<'a> Draw for InventoryPanel<'a> {
+    fn draw(&self) -> DrawCommand {
+        const SLOT: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+        const SELECTED: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+        let size = 32.0;
+        let gap = 6.0;
+        let border = 3.0;
+        let inset = 6.0;
+
+        let left = self.x - 0.5 * (self.capacity as f64 * (size + gap) - gap);
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for i in 0..self.capacity {
+            let min = (left + i as f64 * (size + gap), self.y - size);
+            let max = (min.0 + size, self.y);
+
+            // Quads are kept from overlapping so the draw order does not matter
+            if i == self.selected {
+                push_frame(&mut vertices, &mut indices,
+                           (min.0 - border, min.1 - border), (max.0 + border, max.1 + border), border, SELECTED);
+            }
+
+            if let Some(item) = self.items.get(i) {
+                push_frame(&mut vertices, &mut indices, min, max, inset, SLOT);
+                push_quad(&mut vertices, &mut indices,
+                          (min.0 + inset, min.1 + inset), (max.0 - inset, max.1 - inset), item.color());
+            } else {
+                push_quad(&mut vertices, &mut indices, min, max, SLOT);
+            }
+        }
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// A titled column of menu entries centered on a point, entries with a value show it as a bar on their right half
+pub struct MenuPanel<'a> {
+    pub x: f64,
+    pub y: f64,
+
+    pub title: &'a str,
+    pub entries: &'a [(&'a str, Option<f64>)],
+    pub selected: usize,
+}
+
+
+impl<'a> Draw for MenuPanel<'a> {
+    fn draw(&self) -> DrawCommand {
+        const ENTRY: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+        const SELECTED: [f32; 4] = [0.8, 0.7, 0.3, 1.0];
+        const EMPTY: [f32; 4] = [0.1, 0.1, 0.1, 1.0];
+        const VALUE: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+        const LABEL: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+
+        let width = 360.0;
+        let height = 40.0;
+        let gap = 16.0;
+        let border = 3.0;
+        let inset = 10.0;
+
+        let title_scale = 4.0;
+        let label_scale = 2.0;
+
+        let left = self.x - width / 2.0;
+        let top = self.y - 0.5 * (self.entries.len() as f64 * (height + gap) - gap);
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        push_text(&mut vertices, &mut indices,
+                  self.x - text_width(self.title, title_scale) / 2.0,
+                  top - 2.0 * gap - GLYPH_HEIGHT as f64 * title_scale,
+                  self.title, title_scale, LABEL);
+
+        for (i, &(label, value)) in self.entries.iter().enumerate() {
+            let min = (left, top + i as f64 * (height + gap));
+            let max = (left + width, min.1 + height);
+
+            let color = if i == self.selected { SELECTED } else { ENTRY };
+            push_frame(&mut vertices, &mut indices, min, max, border, color);
+
+            push_text(&mut vertices, &mut indices,
+                      min.0 + 2.0 * inset, min.1 + 0.5 * (height - GLYPH_HEIGHT as f64 * label_scale),
+                      label, label_scale, LABEL);
+
+            if let Some(value) = value {
+                let fill = value.clamp(0.0, 1.0);
+
+                let bar_min = (self.x, min.1 + inset);
+                let bar_max = (max.0 - inset, max.1 - inset);
+                let split = bar_min.0 + fill * (bar_max.0 - bar_min.0);
+
+                push_quad(&mut vertices, &mut indices, (split, bar_min.1), bar_max, EMPTY);
+
+                if fill > 0.0 {
+                    push_quad(&mut vertices, &mut indices, bar_min, (split, bar_max.1), VALUE);
+                }
+            }
+        }
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use graphics_3d::{
+    trap::{
+        Vector3
+    },
+    Color
+};
+
+use bounding_box::BoundingBox;
+
+
+/// Seconds before a dropped item can be picked up again
+const DROP_DELAY: f64 = 1.0;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ItemKind {
+    HealthPack,
+    Ammo,
+    Key,
+
+    /// Index of the weapon in the arsenal
+    Weapon(usize),
+}
+
+
+impl ItemKind {
+    pub fn color(&self) -> [f32; 4] {
+        match *self {
+            ItemKind::HealthPack => [0.9, 0.2, 0.2, 1.0],
+            ItemKind::Ammo => [0.8, 0.7, 0.3, 1.0],
+            ItemKind::Key => [1.0, 0.9, 0.1, 1.0],
+            ItemKind::Weapon(_) => [0.6, 0.6, 0.7, 1.0],
+        }
+    }
+}
+
+
+
+/// An item lying in the world waiting to be picked up
+pub struct Pickup {
+    pub kind: ItemKind,
+    pub position: Vector3,
+
+    delay: f64,
+}
+
+
+impl Pickup {
+    pub fn new(kind: ItemKind, position: Vector3) -> Pickup {
+        Pickup {
+            kind,
+            position,
+
+            delay: 0.0,
+        }
+    }
+
+    pub fn dropped(kind: ItemKind, position: Vector3) -> Pickup {
+        Pickup {
+            delay: DROP_DELAY,
+            ..Pickup::new(kind, position)
+        }
+    }
+
+
+    pub fn hull(&self) -> BoundingBox {
+        let [r, g, b, a] = self.kind.color();
+
+        BoundingBox {
+            color: Some(Color::new(r as f64, g as f64, b as f64, a as f64)),
+            ..BoundingBox::cube(self.position, 0.2)
+        }
+    }
+
+
+    pub fn update(&mut self, dt: f64) {
+        if self.delay > 0.0 {
+            self.delay -= dt;
+        }
+    }
+
+    pub fn can_pick_up(&self) -> bool {
+        self.delay <= 0.0
+    }
+}
+
+
+/// The items carried by the player
+pub struct Inventory {
+    items: Vec<ItemKind>,
+    capacity: usize,
+
+    selected: usize,
+}
+
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Inventory {
+        Inventory {
+            items: Vec::new(),
+            capacity,
+
+            selected: 0,
+        }
+    }
+
+
+    pub fn items(&self) -> &[ItemKind] {
+        &self.items
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+
+    /// Store an item, returns false if the inventory is full
+    pub fn add(&mut self, kind: ItemKind) -> bool {
+        if self.items.len() < self.capacity {
+            self.items.push(kind);
+            true
+        } else {
+            false
+        }
+    }
+
+
+    pub fn selected(&self) -> Option<ItemKind> {
+        self.items.get(self.selected).cloned()
+    }
+
+    /// Remove the selected item from the inventory
+    pub fn take_selected(&mut self) -> Option<ItemKind> {
+        if self.selected < self.items.len() {
+            Some(self.items.remove(self.selected))
+        } else {
+            None
+        }
+    }
+
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.capacity;
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = (self.selected + self.capacity - 1) % self.capacity;
+    }
+}
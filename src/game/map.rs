@@ -0,0 +1,142 @@
+use graphics_3d::{
+    Draw,
+    DrawCommand,
+    Vertex,
+
+    trap::Vector2,
+};
+
+use bounding_box::Rectangle;
+
+use super::world::{
+    World,
+    Visibility,
+};
+use super::hud::push_quad;
+
+
+/// Places points on the floor on screen, with the positive z axis pointing up the screen
+#[derive(Copy, Clone)]
+pub struct MapView {
+    /// The point on the floor shown at the center
+    pub focus: Vector2,
+
+    /// Pixels per unit on the floor
+    pub scale: f64,
+
+    /// The area of the screen the map is drawn in
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+}
+
+
+impl MapView {
+    fn to_screen(self, point: Vector2) -> (f64, f64) {
+        let center = (0.5 * (self.min.0 + self.max.0), 0.5 * (self.min.1 + self.max.1));
+
+        // Looking down from above, the positive x axis points left
+        (
+            center.0 - (point.x - self.focus.x) * self.scale,
+            center.1 - (point.y - self.focus.y) * self.scale,
+        )
+    }
+
+    /// A rectangle on the floor as a rectangle on screen, clipped to the map
+    fn project(&self, rectangle: &Rectangle) -> Option<((f64, f64), (f64, f64))> {
+        let a = self.to_screen(rectangle.min);
+        let b = self.to_screen(rectangle.max);
+
+        let min = (a.0.min(b.0).max(self.min.0), a.1.min(b.1).max(self.min.1));
+        let max = (a.0.max(b.0).min(self.max.0), a.1.max(b.1).min(self.max.1));
+
+        if min.0 < max.0 && min.1 < max.1 {
+            Some((min, max))
+        } else {
+            None
+        }
+    }
+}
+
+
+
+/// The floors of all rooms and passages the player knows about, seen from above
+pub struct MapLayout<'a> {
+    pub view: MapView,
+    pub world: &'a World,
+}
+
+
+impl<'a> Draw for MapLayout<'a> {
+    fn draw(&self) -> DrawCommand {
+        const EXPLORED: [f32; 4] = [0.45, 0.45, 0.5, 1.0];
+        const SEEN: [f32; 4] = [0.2, 0.2, 0.25, 1.0];
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for (floor, visibility) in self.world.floors() {
+            let color = match visibility {
+                Visibility::Hidden => continue,
+                Visibility::Seen => SEEN,
+                Visibility::Explored => EXPLORED,
+            };
+
+            if let Some((min, max)) = self.view.project(&floor) {
+                push_quad(&mut vertices, &mut indices, min, max, color);
+            }
+        }
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices,
+        }
+    }
+}
+
+
+
+/// An arrow showing where the player is and which way they are facing
+pub struct MapMarker {
+    pub view: MapView,
+
+    pub position: Vector2,
+    pub direction: Vector2,
+}
+
+
+impl Draw for MapMarker {
+    fn draw(&self) -> DrawCommand {
+        const COLOR: [f32; 4] = [0.9, 0.8, 0.2, 1.0];
+
+        let length = 12.0;
+        let width = 7.0;
+
+        let (x, y) = self.view.to_screen(self.position);
+
+        // Screen directions are mirrored like the floor
+        let magnitude = self.direction.x.hypot(self.direction.y);
+        let (dx, dy) = if magnitude > 0.0 {
+            (-self.direction.x / magnitude, -self.direction.y / magnitude)
+        } else {
+            (0.0, -1.0)
+        };
+
+        let point = |forward: f64, side: f64| {
+            Vertex::new([
+                (x + forward * dx - side * dy) as f32,
+                (y + forward * dy + side * dx) as f32,
+                0.0
+            ]).with_color(COLOR)
+        };
+
+        DrawCommand::IndexedVertices {
+            vertices: vec![
+                point(length * 0.6, 0.0),
+                point(-length * 0.4, width * 0.5),
+                point(-length * 0.4, -width * 0.5),
+            ],
+
+            indices: vec![0, 1, 2],
+        }
+    }
+}
@@ -1,84 +1,345 @@
 
 
-use std::{
-    collections::{
-        HashSet
-    }
+use graphics_3d::*;
+use graphics_3d::trap::{
+    Vector3,
+    Vector2,
 };
 
-use graphics_3d::*;
-use graphics_3d::{
-    trap::{
-        Vector3
-    },
+use bounding_box::{
+    BoundingBox,
+    Rectangle,
+    Frustum,
+};
 
-    glutin::{
-        Event,
-        WindowEvent,
-        DeviceEvent,
+use rand::{
+    SeedableRng,
+    rngs::StdRng,
+};
 
-        KeyboardInput,
+use camera::{
+    Camera,
+    CameraMode,
+    CameraEffects,
+};
 
-        VirtualKeyCode,
-        ElementState,
-        MouseButton,
-    }
+use lighting::{
+    Lighting,
+    PointLight,
+    Lit,
+    Fog,
+    FogMode,
+};
+
+use mesh::Mesh;
+
+use replay::{
+    Recorder,
+    Setting,
+};
+
+use scheduler::FixedStep;
+
+use input::{
+    Input,
+    InputEvent,
+    ActionEvent,
+    Action,
+    Binding,
+    Bindings,
+    BINDINGS_PATH,
 };
 
-use bounding_box::BoundingBox;
 
-use camera::Camera;
+/// Mass of the player at size 1, used when pushing props around
+const PLAYER_MASS: f64 = 70.0;
+
+/// Landing faster than this hurts, scaled by the square root of the size like the jump speed
+const FALL_SAFE_SPEED: f64 = 9.0;
+
+/// Damage per unit of landing speed above the safe speed
+const FALL_DAMAGE: f64 = 10.0;
+
+/// Falling below this height is fatal
+const KILL_HEIGHT: f64 = -50.0;
+
+/// Seconds spent dead before respawning
+const RESPAWN_DELAY: f64 = 3.0;
+
+/// Health restored by a health pack
+const HEALTH_PACK: f64 = 35.0;
+
+/// Where the player respawns until they reach a checkpoint
+const START_POSITION: Vector3 = Vector3 { x: 0.0, y: 1.8, z: 0.0 };
+
+/// Mixed into the seed so items are placed with different random numbers than the rooms are generated with
+const ITEM_SEED: u64 = 0x5eed;
+
+/// Speed of the player at size 1, doubled while sprinting
+const WALK_SPEED: f64 = 6.0;
+
+/// Landing faster than this shakes the camera, with full trauma at the speed that starts hurting
+const LANDING_SHAKE_SPEED: f64 = 4.0;
+
+/// Least difference in speed between starting to shake and full trauma,
+/// small players start hurting at lower speeds than the shake starts
+const MIN_LANDING_SHAKE_RANGE: f64 = 1.0;
+
+/// Seconds it takes smoothed mouse movement to mostly reach the camera
+const MOUSE_SMOOTHING_TIME: f64 = 0.03;
+
+/// Distance from the player to the camera in third person, unless a wall is closer
+const DEFAULT_BOOM_LENGTH: f64 = 3.0;
+
+/// How far the camera is kept in front of the wall that shortened the boom
+const BOOM_MARGIN: f64 = 0.2;
+
+/// The light of a shot, shining from in front of the player's eyes
+const MUZZLE_FLASH_DISTANCE: f64 = 0.5;
+const MUZZLE_FLASH_RADIUS: f64 = 10.0;
+const MUZZLE_FLASH_COLOR: [f32; 3] = [1.4, 1.1, 0.6];
+
+/// Simulation steps per second
+const TICK_RATE: f64 = 120.0;
+
+/// Width and height of the map in the corner of the screen in pixels
+const MINIMAP_SIZE: f64 = 200.0;
+
+/// Pixels per unit on the map in the corner of the screen
+const MINIMAP_SCALE: f64 = 3.0;
+
+/// Smallest and largest pixels per unit on the full screen map, and the factor each zoom step changes it by
+const MAP_SCALE_RANGE: (f64, f64) = (1.0, 40.0);
+const MAP_ZOOM_STEP: f64 = 1.25;
+
+/// Radians turned per pixel of mouse movement
+pub const DEFAULT_SENSITIVITY: f64 = 0.001;
+
+/// Vertical field of view in degrees
+pub const DEFAULT_FIELD_OF_VIEW: f64 = 70.0;
+
+/// Distance to the far plane, nothing further away is drawn
+pub const DEFAULT_DRAW_DISTANCE: f64 = 100.0;
+
+/// Where linear fog starts, as a fraction of the draw distance
+const FOG_START: f64 = 0.4;
+
+/// The colour behind everything, distant surfaces fade into it
+const CLEAR_COLOR: [f32; 3] = [0.01, 0.01, 0.01];
+
+/// Radians per second turned with the look stick fully deflected
+const STICK_LOOK_SPEED: f64 = 3.0;
 
 
 mod world;
 use self::world::*;
 
+mod physics;
+use self::physics::{
+    Physics,
+    PropKind,
+    GRAVITY,
+};
+
+mod hud;
+use self::hud::*;
+
+mod map;
+use self::map::{
+    MapView,
+    MapLayout,
+    MapMarker,
+};
+
+mod text;
+use self::text::{
+    Text,
+    text_width,
+};
+
+mod health;
+use self::health::Health;
+
+mod enemies;
+use self::enemies::{
+    Enemy,
+    ENEMY_MASS,
+};
+
+mod pads;
+use self::pads::{
+    Pad,
+    PadKind,
+    SPIKE_DAMAGE,
+};
+
+mod weapons;
+use self::weapons::{
+    Arsenal,
+    WeaponDef,
+    FireMode,
+    Projectile,
+    WEAPONS,
+};
+
+mod states;
+pub use self::states::StateStack;
+
+mod console;
+use self::console::Console;
+
+mod items;
+use self::items::{
+    Inventory,
+    ItemKind,
+    Pickup,
+};
+
 
 
 pub struct GameState {
-    running: bool,
+    /// Set while a menu is open, the game is not updated and ignores new presses
+    paused: bool,
 
     time: f64,
-    accumulator: f64,
+    scheduler: FixedStep,
 
-    pressed_keys: HashSet<VirtualKeyCode>,
+    input: Input,
 
     camera: Camera,
-    perspective: Projection,
+
+    /// The camera before the last step, drawn blended with the current one
+    previous_camera: Camera,
+
+    camera_mode: CameraMode,
+
+    /// Flown around in spectator mode, detached from the player.
+    /// Orbiting uses its orientation to look at the player.
+    spectator: Camera,
+    previous_spectator: Camera,
+
+    /// How far behind the player the camera sits when it is not in their eyes
+    boom_length: f64,
+    effects: CameraEffects,
+
+    mouse_sensitivity: f64,
+    invert_y: bool,
+
+    /// Spread mouse movement over a few steps instead of turning all at once
+    mouse_smoothing: bool,
+    /// Mouse movement that hasn't reached the camera yet
+    pending_look: (f64, f64),
+
+    field_of_view: f64,
+    draw_distance: f64,
+
     orthographic: Projection,
 
     velocity: Vector3,
     grounded: bool,
 
+    health: Health,
+    respawn_point: Vector3,
+    respawn_timer: Option<f64>,
+
     boxes: Vec<BoundingBox>,
     size: f64,
 
+    screen_width: f64,
+    screen_height: f64,
+
     crosshair: Crosshair,
+    fps: Option<f64>,
+
+    /// Things skipped in the last frame because they were outside the view
+    culled: usize,
+
+    /// Whether the map covers the whole screen instead of a corner
+    map_open: bool,
+    map_scale: f64,
 
 
     world: World,
-    particles: Vec<Particle>
+    lighting: Lighting,
+    particles: Vec<Particle>,
+
+    physics: Physics,
+
+    arsenal: Arsenal,
+    projectiles: Vec<Projectile>,
+
+    pads: Vec<Pad>,
+    enemies: Vec<Enemy>,
+
+    inventory: Inventory,
+    pickups: Vec<Pickup>,
+
+    seed: u64,
+    rng: StdRng,
+
+    recorder: Option<Recorder>,
+
+    console: Console,
+}
+
+
+/// Create a random number generator that always gives the same numbers for the same seed
+pub fn seeded_rng(seed: u64) -> StdRng {
+    let mut bytes = [0; 32];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (seed >> (8 * (i % 8))) as u8 ^ (i / 8) as u8;
+    }
+
+    StdRng::from_seed(bytes)
 }
 
 
 impl GameState {
+    /// Start a game in a randomly generated world
     pub fn new() -> GameState {
+        use rand::{
+            thread_rng,
+            Rng
+        };
+
+        Self::with_seed(thread_rng().gen())
+    }
+
+    /// Start a game where everything random is derived from a seed
+    pub fn with_seed(seed: u64) -> GameState {
+        let camera = Camera::new(Vector3::new(0.0, 1.0, -2.0));
 
-        GameState {
-            running: true,
+        let mut game = GameState {
+            paused: false,
 
             time: 0.0,
-            accumulator: 0.0,
+            scheduler: FixedStep::new(TICK_RATE),
 
-            pressed_keys: HashSet::new(),
+            input: Input::new(Bindings::load(BINDINGS_PATH).unwrap_or_else(|e| {
+                println!("Using default bindings: {}", e);
+                Bindings::default()
+            })),
+
+            camera,
+            previous_camera: camera,
+
+            camera_mode: CameraMode::Walk,
+
+            spectator: camera,
+            previous_spectator: camera,
+
+            boom_length: DEFAULT_BOOM_LENGTH,
+            effects: CameraEffects::new(),
+
+            mouse_sensitivity: DEFAULT_SENSITIVITY,
+            invert_y: false,
+            mouse_smoothing: false,
+            pending_look: (0.0, 0.0),
+
+            field_of_view: DEFAULT_FIELD_OF_VIEW,
+            draw_distance: DEFAULT_DRAW_DISTANCE,
 
-            camera: Camera::new(Vector3::new(0.0, 1.0, -2.0)),
-            perspective: Projection::Perspective {
-                fov: 70.0,
-                aspect: 1.0,
-                near: 0.01,
-                far: 100.0,
-            },
             orthographic: Projection::Orthographic {
                 left: -1.0,
                 right: 1.0,
@@ -92,449 +353,1569 @@ impl GameState {
             velocity: Vector3::new(0.0, 0.0, 0.0),
             grounded: false,
 
+            health: Health::new(100.0, 100.0),
+            respawn_point: START_POSITION,
+            respawn_timer: None,
+
             boxes: vec![],
             size: 1.0,
 
+            screen_width: 0.0,
+            screen_height: 0.0,
+
             crosshair: Crosshair {
                 x: 0.0,
                 y: 0.0,
                 size: 25.0,
                 width: 2.0,
             },
+            fps: None,
+
+            culled: 0,
+
+            map_open: false,
+            map_scale: 8.0,
+
+            world: World::new(seed),
+            // Fog shades every vertex on the CPU each frame, so it is left for players to turn on
+            lighting: Lighting::new().with_fog(Fog {
+                mode: FogMode::Off,
+                color: CLEAR_COLOR,
+                start: FOG_START * DEFAULT_DRAW_DISTANCE,
+                end: DEFAULT_DRAW_DISTANCE,
+            }),
+            particles: Vec::new(),
+
+            physics: Physics::new(),
+
+            arsenal: Arsenal::new(),
+            projectiles: Vec::new(),
+
+            pads: Vec::new(),
+            enemies: Vec::new(),
+
+            inventory: Inventory::new(8),
+            pickups: Vec::new(),
+
+            seed,
+            rng: seeded_rng(seed ^ ITEM_SEED),
+
+            recorder: None,
+
+            console: Console::new(),
+        };
+
+        game.health.add_armour(50.0);
+
+        game.populate_start();
+
+        game
+    }
+
+
+    /// Replace the world with one generated from another seed, the player keeps their items
+    fn regenerate(&mut self, seed: u64) {
+        self.replace_world(World::new(seed));
+    }
+
+    /// Start again in the first room of another world, the player keeps their items
+    fn replace_world(&mut self, world: World) {
+        self.seed = world.seed();
+        self.rng = seeded_rng(self.seed ^ ITEM_SEED);
+
+        self.world = world;
+        self.physics = Physics::new();
+
+        self.particles.clear();
+        self.projectiles.clear();
+        self.pads.clear();
+        self.enemies.clear();
+        self.pickups.clear();
+
+        self.respawn_point = START_POSITION;
+        self.teleport(START_POSITION);
+
+        self.populate_start();
+    }
+
+    fn populate_start(&mut self) {
+        let start = self.world.start_floor();
+        self.populate_room(start);
+
+        // Reveal the starting room so it can be seen behind the main menu
+        self.explore();
+    }
+
+    fn get_boxes(time: f64) -> Vec<BoundingBox> {
+        let count = 4;
+
+        let mut boxes = Vec::new();
+
+        let margin: i32 = 0;
+
+        for x in -count + margin..count - margin+1 {
+            for y in 3..count*2+4 {
+                for z in -count + margin..count - margin +1 {
+                    boxes.push(BoundingBox::cube(
+                        Vector3::new(x as f64, y as f64, z as f64),
+                        ((time + (x + y + z) as f64).sin() * (time * 0.5).cos() * 0.5 + 0.5) * 0.35 + 0.05
+                    ));
+                }
+            }
+        }
+
+        boxes
+    }
+
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn dead(&self) -> bool {
+        self.health.dead()
+    }
+
+    /// Seconds until the player respawns, if they are dead
+    pub fn respawn_time(&self) -> Option<f64> {
+        self.respawn_timer
+    }
+
+    /// Frames per second shown in the corner of the screen
+    pub fn set_fps(&mut self, fps: f64) {
+        self.fps = Some(fps);
+    }
+
+    pub fn screen_size(&self) -> (f64, f64) {
+        (self.screen_width, self.screen_height)
+    }
+
+
+    pub fn mouse_sensitivity(&self) -> f64 {
+        self.mouse_sensitivity
+    }
+
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f64) {
+        self.mouse_sensitivity = sensitivity;
+        self.record_setting(Setting::Sensitivity(sensitivity));
+    }
+
+    pub fn field_of_view(&self) -> f64 {
+        self.field_of_view
+    }
+
+    pub fn set_field_of_view(&mut self, fov: f64) {
+        self.field_of_view = fov;
+    }
+
+    pub fn draw_distance(&self) -> f64 {
+        self.draw_distance
+    }
+
+    /// Move the far plane, with the fog ending just in front of it.
+    /// The fog keeps starting where it did, unless that is past the new end.
+    pub fn set_draw_distance(&mut self, distance: f64) {
+        self.draw_distance = distance;
+        self.lighting.fog.start = self.lighting.fog.start.min(distance);
+        self.lighting.fog.end = distance;
+    }
+
+    pub fn fog_start(&self) -> f64 {
+        self.lighting.fog.start
+    }
+
+    /// Where linear fog starts, somewhere before the draw distance
+    pub fn set_fog_start(&mut self, start: f64) {
+        self.lighting.fog.start = start.clamp(0.0, self.draw_distance);
+    }
+
+    pub fn fog_mode(&self) -> FogMode {
+        self.lighting.fog.mode
+    }
+
+    pub fn set_fog_mode(&mut self, mode: FogMode) {
+        self.lighting.fog.mode = mode;
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.invert_y
+    }
+
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+        self.record_setting(Setting::InvertY(invert_y));
+    }
+
+    pub fn mouse_smoothing(&self) -> bool {
+        self.mouse_smoothing
+    }
+
+    pub fn set_mouse_smoothing(&mut self, smoothing: bool) {
+        self.mouse_smoothing = smoothing;
+        self.pending_look = (0.0, 0.0);
+        self.record_setting(Setting::Smoothing(smoothing));
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.bindings(&bindings);
+        }
+
+        self.input.set_bindings(bindings);
+    }
+
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn player_position(&self) -> Vector3 {
+        self.camera.position
+    }
+
+
+    /// Write all input and frame times to a recorder until the recording is stopped
+    pub fn start_recording(&mut self, mut recorder: Recorder) {
+        // The recorded input only does the same again with the same bindings and settings
+        recorder.bindings(self.input.bindings());
+        recorder.setting(Setting::Sensitivity(self.mouse_sensitivity));
+        recorder.setting(Setting::InvertY(self.invert_y));
+        recorder.setting(Setting::Smoothing(self.mouse_smoothing));
+
+        self.recorder = Some(recorder);
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(e) = recorder.finish(self.camera.position) {
+                println!("Failed to save recording: {}", e);
+            }
+        }
+    }
+
+
+    /// Changed settings are written to the recording so a replay changes them at the same moment
+    fn record_setting(&mut self, setting: Setting) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.setting(setting);
+        }
+    }
+
+
+    pub fn update(&mut self, dt: f64) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.frame(dt);
+        }
+
+        self.simulate(dt);
+    }
+
+    /// Advance the game without recording the frame
+    fn simulate(&mut self, dt: f64) {
+        let steps = self.scheduler.advance(dt);
+        let time_step = self.scheduler.time_step();
+
+        for _ in 0..steps {
+            self.step(time_step);
+        }
+    }
+
+
+    /// Advance the simulation by one fixed time step
+    fn step(&mut self, dt: f64) {
+        self.previous_camera = self.camera;
+        self.previous_spectator = self.spectator;
+        self.time += dt;
+
+        self.check_player_movement(dt);
+
+        self.explore();
+
+
+        self.update_particles(dt);
+
+        self.physics.step(dt, &self.world);
+
+        if let Some(weapon) = self.arsenal.update(dt) {
+            self.fire(weapon);
+        }
+
+        self.update_projectiles(dt);
+
+
+        if self.action_down(Action::Shrink) {
+            self.size -= 4.0 * dt;
+
+            if self.size < 0.1 {
+                self.size = 0.1;
+            }
+        }
+
+        if self.action_down(Action::Grow) {
+            self.size += 4.0 * dt;
+
+            if self.size > 4.0 {
+                self.size = 4.0;
+            }
+        }
+
+
+        if !self.camera_mode.flying() {
+            self.check_collisions();
+
+            self.check_pads(dt);
+        }
+
+        self.update_enemies(dt);
+
+        self.update_effects(dt);
+
+        self.check_pickups(dt);
+
+        self.update_death(dt);
+    }
+
+
+    fn check_player_movement(&mut self, dt: f64) {
+        let (look_x, mut look_y) = self.input.look();
+        if self.invert_y {
+            look_y = -look_y;
+        }
+
+        self.controlled_camera().rotate(-look_x * STICK_LOOK_SPEED * dt, look_y * STICK_LOOK_SPEED * dt);
+
+        // Turn part of the way towards where the mouse has moved
+        if self.mouse_smoothing {
+            let fraction = 1.0 - (-dt / MOUSE_SMOOTHING_TIME).exp();
+            let (dx, dy) = self.pending_look;
+
+            self.controlled_camera().rotate(fraction * dx, fraction * dy);
+            self.pending_look = ((1.0 - fraction) * dx, (1.0 - fraction) * dy);
+        }
+
+        match self.camera_mode {
+            CameraMode::Walk | CameraMode::ThirdPerson => {
+                let movement = self.movement(&self.camera, false);
+
+                if !self.health.dead() {
+                    self.camera.position += dt * movement;
+                }
+            }
+
+            // Walk relative to the view, the player keeps facing the same way
+            CameraMode::Orbit => {
+                let movement = self.movement(&self.spectator, false);
+
+                if !self.health.dead() {
+                    self.camera.position += dt * movement;
+                }
+            }
+
+            CameraMode::Noclip => {
+                let movement = self.movement(&self.camera, true);
+
+                if !self.health.dead() {
+                    self.camera.position += dt * movement;
+                }
+
+                return;
+            }
+
+            // The player's body is left to fall on its own
+            CameraMode::Spectator => {
+                let movement = self.movement(&self.spectator, true);
+                self.spectator.position += dt * movement;
+            }
+        }
+
+        self.velocity.y -= dt * GRAVITY;
+        self.camera.position += dt * self.velocity;
+    }
+
+    /// The velocity the movement actions ask for, relative to where a camera is looking.
+    /// When flying, forward is the full view direction instead of along the floor.
+    fn movement(&self, camera: &Camera, flying: bool) -> Vector3 {
+        let mut move_direction = Vector3::new(0.0, 0.0, 0.0);
+        let mut speed = WALK_SPEED * self.size;
+
+        let right = camera.direction().cross(Vector3::new(0.0, 1.0, 0.0));
+
+        let forward = if flying {
+            camera.direction()
+        } else {
+            Vector3::new(0.0, 1.0, 0.0).cross(right)
+        };
+
+        if self.action_down(Action::MoveForward) {
+            move_direction += forward;
+        }
+        if self.action_down(Action::MoveBackward) {
+            move_direction -= forward;
+        }
+
+        if self.action_down(Action::MoveLeft) {
+            move_direction -= right
+        }
+        if self.action_down(Action::MoveRight) {
+            move_direction += right
+        }
+
+        if self.action_down(Action::Sprint) {
+            speed *= 2.0;
+        }
+
+        if move_direction.dot(move_direction) > 0.0 {
+            move_direction = move_direction.normal();
+        } else {
+            // The stick allows walking slower than full speed
+            let (stick_x, stick_y) = self.input.movement();
+            move_direction = stick_x * right + stick_y * forward;
+        }
+
+        speed * move_direction
+    }
+
+    /// The camera the mouse and sticks turn
+    fn controlled_camera(&mut self) -> &mut Camera {
+        if self.camera_mode.detached() {
+            &mut self.spectator
+        } else {
+            &mut self.camera
+        }
+    }
+
+    fn set_camera_mode(&mut self, mode: CameraMode) {
+        // A detached view starts out looking the way the player is
+        if mode.detached() && !self.camera_mode.detached() {
+            self.spectator = self.camera;
+            self.previous_spectator = self.previous_camera;
+        }
+
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        self.camera_mode = mode;
+    }
+
+    fn update_effects(&mut self, dt: f64) {
+        let moved = self.camera.position - self.previous_camera.position;
+
+        // The head only bobs while walking on something
+        let walk_speed = if self.grounded && self.camera_mode.walking() {
+            moved.x.hypot(moved.z) / (dt * WALK_SPEED * self.size)
+        } else {
+            0.0
+        };
+
+        let sprinting = self.action_down(Action::Sprint) && walk_speed > 1.0;
+
+        self.effects.update(dt, walk_speed, sprinting);
+        self.lighting.update(dt);
+    }
+
+    fn explore(&mut self) {
+        for floor in self.world.explore(self.camera.position) {
+            self.populate_room(floor);
+            self.spawn_enemies(floor);
+        }
+
+        self.world.reveal(self.camera.position);
+    }
+
+
+    /// Scatter props across the floor of a room
+    fn populate_room(&mut self, floor: Rectangle) {
+        use rand::Rng;
+
+        let rng = &mut self.rng;
+
+        let margin = 1.0;
+
+        for _ in 0..rng.gen_range(1, 4) {
+            let kind = if rng.gen_range(0, 2) == 0 { PropKind::Crate } else { PropKind::Barrel };
+
+            let position = Vector3::new(
+                rng.gen_range(floor.min.x + margin, floor.max.x - margin),
+                1.0,
+                rng.gen_range(floor.min.y + margin, floor.max.y - margin),
+            );
+
+            self.physics.spawn(kind, position);
+        }
+
+        for &(kind, chance) in [(PadKind::Spikes, 0.4), (PadKind::Checkpoint, 0.25)].iter() {
+            if rng.gen_range(0.0, 1.0) < chance {
+                let center = Vector2::new(
+                    rng.gen_range(floor.min.x + margin, floor.max.x - margin),
+                    rng.gen_range(floor.min.y + margin, floor.max.y - margin),
+                );
+
+                self.pads.push(Pad::new(kind, center));
+            }
+        }
+
+        let weapon = ItemKind::Weapon(rng.gen_range(1, WEAPONS.len()));
+        let items = [
+            (ItemKind::HealthPack, 0.5),
+            (ItemKind::Ammo, 0.5),
+            (ItemKind::Key, 0.15),
+            (weapon, 0.1),
+        ];
+
+        for &(kind, chance) in items.iter() {
+            if rng.gen_range(0.0, 1.0) < chance {
+                let position = Vector3::new(
+                    rng.gen_range(floor.min.x + margin, floor.max.x - margin),
+                    0.2,
+                    rng.gen_range(floor.min.y + margin, floor.max.y - margin),
+                );
+
+                self.pickups.push(Pickup::new(kind, position));
+            }
+        }
+    }
+
+    /// Place enemies in a newly generated room, never in the room the player starts in
+    fn spawn_enemies(&mut self, floor: Rectangle) {
+        use rand::Rng;
+
+        let rng = &mut self.rng;
+        let margin = 1.5;
+
+        for _ in 0..rng.gen_range(0, 3) {
+            let position = Vector3::new(
+                rng.gen_range(floor.min.x + margin, floor.max.x - margin),
+                0.0,
+                rng.gen_range(floor.min.y + margin, floor.max.y - margin),
+            );
+
+            self.enemies.push(Enemy::new(position));
+        }
+    }
+
+    fn update_particles(&mut self, dt: f64) {
+        let mut i = 0;
+        while i < self.particles.len() {
+            let remove = {
+                let particle = &mut self.particles[i];
+                particle.previous_position = particle.position;
+                particle.position += dt * particle.velocity;
+
+                particle.velocity -= dt * 3.5 * particle.velocity;
+
+                particle.size -= 0.125 * dt;
+                if particle.size < 0.00 {
+                    true
+                } else {
+                    false
+                }
+            };
+
+            if remove {
+                self.particles.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn check_collisions(&mut self) {
+        self.grounded = false;
+
+        // The fastest downward speed stopped by the ground this step
+        let mut landing_speed: f64 = 0.0;
+
+        for collider in self.world.get_colliders() {
+            let hull = self.get_hull();
+
+            if let Some(resolve) = hull.overlap(collider) {
+                self.camera.position += resolve;
+
+
+                if resolve.y * self.velocity.y < 0.0 {
+                    if resolve.y > 0.0 {
+                        self.grounded = true;
+                        landing_speed = landing_speed.max(-self.velocity.y);
+                    }
+
+                    self.velocity.y = 0.0;
+                }
+            }
+        }
+
+        // Push props out of the way, or stand on them
+        let hull = self.get_hull();
+        for resolve in self.physics.push(&hull, PLAYER_MASS * self.size) {
+            self.camera.position += resolve;
+
+            if resolve.y * self.velocity.y < 0.0 {
+                if resolve.y > 0.0 {
+                    self.grounded = true;
+                    landing_speed = landing_speed.max(-self.velocity.y);
+                }
+
+                self.velocity.y = 0.0;
+            }
+        }
+
+        let safe_speed = FALL_SAFE_SPEED * self.size.sqrt();
+
+        if landing_speed > LANDING_SHAKE_SPEED {
+            let range = (safe_speed - LANDING_SHAKE_SPEED).max(MIN_LANDING_SHAKE_RANGE);
+            self.effects.add_trauma((landing_speed - LANDING_SHAKE_SPEED) / range);
+        }
+
+        if landing_speed > safe_speed {
+            self.health.damage(FALL_DAMAGE * (landing_speed - safe_speed));
+        }
+    }
+
+
+    fn check_pads(&mut self, dt: f64) {
+        let hull = self.get_hull();
+
+        for i in 0..self.pads.len() {
+            if !self.pads[i].touches(&hull) {
+                continue;
+            }
+
+            match self.pads[i].kind {
+                PadKind::Spikes => {
+                    self.health.damage(SPIKE_DAMAGE * dt);
+                }
+
+                PadKind::Checkpoint => {
+                    if !self.pads[i].active {
+                        for pad in self.pads.iter_mut() {
+                            pad.active = false;
+                        }
+
+                        self.pads[i].active = true;
+
+                        let center = self.pads[i].area.center();
+                        self.respawn_point = Vector3::new(center.x, hull.size().y, center.y);
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// Enemies chase the player when they can see them and hit them when close enough
+    fn update_enemies(&mut self, dt: f64) {
+        let hull = self.get_hull();
+        let eye = self.camera.position;
+
+        // Nobody goes after a dead player or one flying through walls
+        let target = !self.health.dead() && self.camera_mode.walking();
+
+        for i in 0..self.enemies.len() {
+            let seen = target && self.enemies[i].in_range(eye) && {
+                let from = self.enemies[i].eye();
+
+                // Hit distances are measured in lengths of the direction, anything past 1 is behind the player
+                self.world.hit_scan(from, eye - from).is_none_or(|hit| { hit.distance > 1.0 })
+            };
+
+            let damage = self.enemies[i].update(dt, &hull, seen);
+
+            // Walls and props stop enemies the same way they stop the player
+            for collider in self.world.get_colliders() {
+                if let Some(resolve) = self.enemies[i].hull().overlap(collider) {
+                    self.enemies[i].push_out(resolve);
+                }
+            }
+
+            let enemy_hull = self.enemies[i].hull();
+            for resolve in self.physics.push(&enemy_hull, ENEMY_MASS) {
+                self.enemies[i].push_out(resolve);
+            }
+
+            if damage > 0.0 {
+                self.health.damage(damage);
+                self.effects.add_trauma(0.3);
+            }
+        }
+    }
+
+    /// Find the closest enemy hit by a ray
+    fn enemy_hit_scan(&self, origin: Vector3, direction: Vector3) -> Option<(f64, Vector3, usize)> {
+        self.enemies.iter().enumerate()
+            .filter_map(|(i, enemy)| {
+                enemy.hull().hit_scan(origin, direction).map(|(distance, normal)| { (distance, normal, i) })
+            })
+            .min_by(|a, b| { a.0.partial_cmp(&b.0).unwrap() })
+    }
+
+    /// Hurt an enemy, bursting it into sparks if it dies
+    fn damage_enemy(&mut self, index: usize, amount: f64) {
+        if self.enemies[index].damage(amount) {
+            let enemy = self.enemies.remove(index);
+            self.spawn_sparks(enemy.hull().center(), Vector3::new(0.0, 1.0, 0.0), 2.0, 300);
+        }
+    }
+
+    fn check_pickups(&mut self, dt: f64) {
+        let hull = self.get_hull();
+
+        let mut i = 0;
+        while i < self.pickups.len() {
+            self.pickups[i].update(dt);
+
+            let picked_up = !self.health.dead() &&
+                self.pickups[i].can_pick_up() &&
+                hull.intersect(&self.pickups[i].hull()) &&
+                self.inventory.add(self.pickups[i].kind);
+
+            if picked_up {
+                self.pickups.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+
+    /// Use the selected item, keeping it if it had no effect
+    fn use_item(&mut self) {
+        let used = match self.inventory.selected() {
+            Some(ItemKind::HealthPack) => self.health.heal(HEALTH_PACK),
+            Some(ItemKind::Ammo) => self.arsenal.add_ammo(),
+            Some(ItemKind::Weapon(index)) => self.arsenal.give(index),
+
+            // There is nothing to unlock with keys yet
+            Some(ItemKind::Key) | None => false,
+        };
+
+        if used {
+            self.inventory.take_selected();
+        }
+    }
+
+    /// Drop the selected item at the player's feet
+    fn drop_item(&mut self) {
+        if let Some(kind) = self.inventory.take_selected() {
+            let forward = Vector3::new(0.0, 1.0, 0.0)
+                .cross(self.camera.direction().cross(Vector3::new(0.0, 1.0, 0.0)))
+                .normal();
+
+            let hull = self.get_hull();
+            let mut position = hull.center() + (hull.size().x + 0.3) * forward;
+            position.y = hull.min.y + 0.2;
+
+            self.pickups.push(Pickup::dropped(kind, position));
+        }
+    }
+
+
+    fn update_death(&mut self, dt: f64) {
+        if self.camera.position.y < KILL_HEIGHT && !self.camera_mode.flying() {
+            self.health.health = 0.0;
+        }
+
+        match self.respawn_timer {
+            None => {
+                if self.health.dead() {
+                    self.respawn_timer = Some(RESPAWN_DELAY);
+                    self.arsenal.set_trigger(false);
+                }
+            }
+
+            Some(remaining) => {
+                if remaining > dt {
+                    self.respawn_timer = Some(remaining - dt);
+                } else {
+                    self.respawn();
+                }
+            }
+        }
+    }
+
+
+    fn respawn(&mut self) {
+        self.respawn_timer = None;
+        self.health.reset();
+
+        let respawn_point = self.respawn_point;
+        self.teleport(respawn_point);
+    }
+
+
+    fn get_hull(&self) -> BoundingBox {
+        self.hull_at(self.camera.position)
+    }
+
+    /// The player's hull with their eyes at a position
+    fn hull_at(&self, position: Vector3) -> BoundingBox {
+        let width = 0.4 * self.size;
+        let height = 1.8 * self.size;
+
+        BoundingBox {
+            min: position - Vector3::new(width, height * 5.0 / 6.0, width),
+            max: position + Vector3::new(width, height / 6.0, width),
+            color: Some(Color::new(1.0, 0.0, 0.0, 1.0))
+        }
+    }
+
+
+    //
+    // Rendering
+    //
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        frame.clear(Color::new(CLEAR_COLOR[0] as f64, CLEAR_COLOR[1] as f64, CLEAR_COLOR[2] as f64, 1.0));
+
+        // Blend between the last two steps so movement stays smooth when the simulation runs slower than the display
+        let alpha = self.scheduler.alpha();
+
+        let player = self.camera.interpolate(&self.previous_camera, alpha);
+
+        let camera = match self.camera_mode {
+            CameraMode::Walk | CameraMode::Noclip => self.effects.apply(&player),
+            CameraMode::ThirdPerson => player.orbit(player.position, self.boom(player.position, player.direction())),
+            CameraMode::Orbit => self.spectator.orbit(player.position, self.boom(player.position, self.spectator.direction())),
+            CameraMode::Spectator => self.spectator.interpolate(&self.previous_spectator, alpha),
+        };
+
+        let projection = self.perspective();
+        let view = camera.view();
+        let frustum = Frustum::new(&projection, &view);
+
+        self.lighting.eye = camera.position;
+
+        frame.set_projection(projection);
+        frame.set_view(view);
+
+        self.draw_scene(frame, alpha, frustum.as_ref());
+
+        self.draw_ui(frame);
+    }
+
+
+    fn draw_scene(&mut self, frame: &mut Frame, alpha: f64, frustum: Option<&Frustum>) {
+        let time = self.time - (1.0 - alpha) * self.scheduler.time_step();
+        self.boxes = Self::get_boxes(time);
+
+        // Skip everything outside the view, counting what was skipped
+        let in_view = |bounds: &BoundingBox| { frustum.is_none_or(|frustum| { frustum.intersects(bounds) }) };
+        let mut culled = 0;
+
+        // Small things that move every frame are gathered into one mesh each, lit and fogged like the rest of the scene
+        let mut boxes = Mesh::new();
+        for b in self.boxes.iter() {
+            if in_view(b) {
+                boxes.push_box(b, &[false; 6]);
+            } else {
+                culled += 1;
+            }
+        }
+
+        // Draw the world, only the rooms that can be seen through the passages from the one the eye is in
+        let rooms = match frustum {
+            Some(frustum) => self.world.portal_rooms(frustum),
+            None => self.world.visible_rooms().collect(),
+        };
+
+        culled += self.world.visible_rooms().count() - rooms.len();
+
+        for room in rooms {
+            frame.draw(&Lit {
+                mesh: room.mesh(),
+                lighting: &self.lighting,
+                lights: room.torches(),
+            });
+            frame.draw(&Lit {
+                mesh: room.flames(),
+                lighting: &self.lighting,
+                lights: &[],
+            });
+        }
+
+        let mut particles = Mesh::new();
+        for particle in self.particles.iter() {
+            let particle = particle.interpolate(alpha);
+
+            if in_view(&particle) {
+                particles.push_box(&particle, &[false; 6]);
+            } else {
+                culled += 1;
+            }
+        }
+
+        // Projectiles burn, they aren't lit but still fade into the fog
+        let mut projectiles = Mesh::glowing();
+        for projectile in self.projectiles.iter() {
+            projectiles.push_box(&projectile.hull(), &[false; 6]);
+        }
+
+        for mesh in [boxes, particles, projectiles].iter() {
+            frame.draw(&Lit {
+                mesh,
+                lighting: &self.lighting,
+                lights: &[],
+            });
+        }
+
+        // Anything in a room the player hasn't seen stays hidden along with the room
+        let world = &self.world;
+        let visible = |position: Vector3| { world.visibility(position) != Visibility::Hidden };
+
+        for body in self.physics.bodies().iter().filter(|body| { visible(body.position) }) {
+            let hull = body.hull();
+
+            if in_view(&hull) {
+                frame.draw(&Lit {
+                    mesh: &Mesh::from_box(&hull),
+                    lighting: &self.lighting,
+                    lights: self.world.lights_at(body.position),
+                });
+            } else {
+                culled += 1;
+            }
+        }
+
+        for enemy in self.enemies.iter().filter(|enemy| { visible(enemy.position) }) {
+            let hull = enemy.interpolate(alpha);
+
+            if in_view(&hull) {
+                frame.draw(&Lit {
+                    mesh: &Mesh::from_box(&hull),
+                    lighting: &self.lighting,
+                    lights: self.world.lights_at(enemy.position),
+                });
+            } else {
+                culled += 1;
+            }
+        }
+
+        for pad in self.pads.iter() {
+            let center = pad.area.center();
+            let position = Vector3::new(center.x, 0.0, center.y);
+
+            if visible(position) {
+                frame.draw(&Lit {
+                    mesh: &Mesh::from_box(&pad.hull()),
+                    lighting: &self.lighting,
+                    lights: self.world.lights_at(position),
+                });
+            }
+        }
+
+        for pickup in self.pickups.iter().filter(|pickup| { visible(pickup.position) }) {
+            frame.draw(&Lit {
+                mesh: &Mesh::from_box(&pickup.hull()),
+                lighting: &self.lighting,
+                lights: self.world.lights_at(pickup.position),
+            });
+        }
+
+        // The player can be seen from outside their body
+        if !self.camera_mode.first_person() {
+            let player = self.camera.interpolate(&self.previous_camera, alpha);
+
+            frame.draw(&Lit {
+                mesh: &Mesh::from_box(&self.hull_at(player.position)),
+                lighting: &self.lighting,
+                lights: self.world.lights_at(player.position),
+            });
+        }
+
+        self.culled = culled;
+    }
+
+
+    /// How far the camera can back away from the player's eyes, looking in a direction,
+    /// before it would pass through a wall
+    fn boom(&self, eye: Vector3, direction: Vector3) -> f64 {
+        match self.world.hit_scan(eye, -1.0 * direction) {
+            Some(ref hit) if hit.distance < self.boom_length + BOOM_MARGIN => (hit.distance - BOOM_MARGIN).max(0.0),
+            _ => self.boom_length
+        }
+    }
+
+
+    /// The projection of the scene, widened while sprinting
+    fn perspective(&self) -> Projection {
+        let aspect = if self.screen_height > 0.0 {
+            self.screen_width / self.screen_height
+        } else {
+            1.0
+        };
+
+        Projection::Perspective {
+            fov: self.field_of_view + self.effects.fov_kick(),
+            aspect,
+            near: 0.01,
+            far: self.draw_distance,
+        }
+    }
+
+
+    /// Prepare to draw in screen space, in pixels from the top left corner
+    pub fn begin_ui(&self, frame: &mut Frame) {
+        frame.set_projection(self.orthographic);
+        frame.set_view(View::None);
+
+        frame.clear_depth();
+    }
 
-            world: World::new(),
-            particles: Vec::new()
+
+    fn draw_ui(&mut self, frame: &mut Frame) {
+        const TEXT: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+        self.begin_ui(frame);
+
+        if let Some(fps) = self.fps {
+            frame.draw(&Text {
+                x: 20.0,
+                y: 20.0,
+                text: &format!("FPS {}", fps.ceil()),
+                scale: 2.0,
+                color: TEXT,
+            });
         }
-    }
 
-    fn get_boxes(&self) -> Vec<BoundingBox> {
-        let count = 4;
+        frame.draw(&Text {
+            x: 20.0,
+            y: 44.0,
+            text: &format!("Rooms {}/{}", self.world.explored_count(), self.world.room_count()),
+            scale: 2.0,
+            color: TEXT,
+        });
 
-        let mut boxes = Vec::new();
+        frame.draw(&Text {
+            x: 20.0,
+            y: 68.0,
+            text: &format!("Culled {}", self.culled),
+            scale: 2.0,
+            color: TEXT,
+        });
 
-        let margin: i32 = 0;
+        if self.camera_mode != CameraMode::Walk {
+            frame.draw(&Text {
+                x: 20.0,
+                y: 92.0,
+                text: self.camera_mode.name(),
+                scale: 2.0,
+                color: TEXT,
+            });
+        }
 
-        for x in -count + margin..count - margin+1 {
-            for y in 3..count*2+4 {
-                for z in -count + margin..count - margin +1 {
-                    boxes.push(BoundingBox::cube(
-                        Vector3::new(x as f64, y as f64, z as f64),
-                        ((self.time + (x + y + z) as f64).sin() * (self.time * 0.5).cos() * 0.5 + 0.5) * 0.35 + 0.05
-                    ));
-                }
-            }
+        if self.health.dead() {
+            return;
         }
 
-        boxes
-    }
+        frame.draw(&self.crosshair);
 
+        frame.draw(&Bar {
+            x: 20.0,
+            y: self.screen_height - 20.0,
+            width: 200.0,
+            height: 16.0,
+            fill: self.health.health / self.health.max_health,
+            color: [0.7, 0.1, 0.1, 1.0],
+        });
 
-    pub fn running(&self) -> bool {
-        self.running
-    }
+        frame.draw(&Text {
+            x: 232.0,
+            y: self.screen_height - 35.0,
+            text: &format!("{}", self.health.health.ceil()),
+            scale: 2.0,
+            color: TEXT,
+        });
 
+        frame.draw(&Bar {
+            x: 20.0,
+            y: self.screen_height - 44.0,
+            width: 200.0,
+            height: 8.0,
+            fill: self.health.armour / self.health.max_armour,
+            color: [0.2, 0.4, 0.8, 1.0],
+        });
 
-    pub fn update(&mut self, dt: f64) {
-        self.time += dt;
-        self.accumulator += dt;
+        frame.draw(&Text {
+            x: 232.0,
+            y: self.screen_height - 55.0,
+            text: &format!("{}", self.health.armour.ceil()),
+            scale: 2.0,
+            color: TEXT,
+        });
 
-        self.boxes = Self::get_boxes(self);
+        frame.draw(&InventoryPanel {
+            x: self.screen_width / 2.0,
+            y: self.screen_height - 20.0,
 
-        const TIME_STEP: f64 = 1.0 / 1000.0;
+            items: self.inventory.items(),
+            capacity: self.inventory.capacity(),
+            selected: self.inventory.selected_index(),
+        });
 
-        while self.accumulator > TIME_STEP {
-            if self.accumulator > 0.5 {
-                self.accumulator = 0.0;
-            } else {
-                self.accumulator -= TIME_STEP;
-            }
+        let weapon = self.arsenal.current();
+        frame.draw(&AmmoCounter {
+            x: self.screen_width - 20.0,
+            y: self.screen_height - 20.0,
 
-            self.check_player_movement(TIME_STEP);
+            ammo: weapon.ammo,
+            magazine: weapon.def.magazine,
+            reloading: weapon.reloading(),
+        });
 
-            self.world.explore(self.camera.position);
+        // Ammo and weapon name stacked above the counter, aligned to its right edge
+        let ammo = format!("{} / {}", weapon.ammo, weapon.reserve);
+        frame.draw(&Text {
+            x: self.screen_width - 20.0 - text_width(&ammo, 2.0),
+            y: self.screen_height - 58.0,
+            text: &ammo,
+            scale: 2.0,
+            color: TEXT,
+        });
 
+        frame.draw(&Text {
+            x: self.screen_width - 20.0 - text_width(weapon.def.name, 2.0),
+            y: self.screen_height - 82.0,
+            text: weapon.def.name,
+            scale: 2.0,
+            color: TEXT,
+        });
 
-            self.update_particles(TIME_STEP);
+        self.draw_map(frame);
+    }
 
 
-            if self.key_down(VirtualKeyCode::Q) {
-                self.size -= 4.0 * TIME_STEP;
+    /// Draw the map in the corner of the screen, or over the whole screen while it is open
+    fn draw_map(&self, frame: &mut Frame) {
+        let border = 2.0;
 
-                if self.size < 0.1 {
-                    self.size = 0.1;
-                }
-            }
+        let (min, max, scale, color) = if self.map_open {
+            ((0.0, 0.0), (self.screen_width, self.screen_height), self.map_scale, [0.0, 0.0, 0.0, 0.85])
+        } else {
+            let right = self.screen_width - 20.0;
+            ((right - MINIMAP_SIZE, 20.0), (right, 20.0 + MINIMAP_SIZE), MINIMAP_SCALE, [0.05, 0.05, 0.05, 1.0])
+        };
 
-            if self.key_down(VirtualKeyCode::E) {
-                self.size += 4.0 * TIME_STEP;
+        frame.draw(&Panel {
+            min,
+            max,
+            color,
+            border: [0.3, 0.3, 0.3, 1.0],
+        });
 
-                if self.size > 4.0 {
-                    self.size = 4.0;
-                }
-            }
+        let view = MapView {
+            focus: Vector2::new(self.camera.position.x, self.camera.position.z),
+            scale,
 
+            min: (min.0 + border, min.1 + border),
+            max: (max.0 - border, max.1 - border),
+        };
 
-            self.check_collisions();
-        }
-    }
+        // The layers overlap, clearing the depth between them keeps each on top of the last
+        frame.clear_depth();
+        frame.draw(&MapLayout {
+            view,
+            world: &self.world,
+        });
 
+        let direction = self.camera.direction();
 
-    fn check_player_movement(&mut self, dt: f64) {
-        let mut move_direction = Vector3::new(0.0, 0.0, 0.0);
-        let mut speed = 6.0 * self.size;
+        frame.clear_depth();
+        frame.draw(&MapMarker {
+            view,
 
-        let right = self.camera.direction().cross(Vector3::new(0.0, 1.0, 0.0));
+            position: view.focus,
+            direction: Vector2::new(direction.x, direction.z),
+        });
+    }
 
-        if self.key_down(VirtualKeyCode::W) {
-            move_direction += Vector3::new(0.0, 1.0, 0.0).cross(right);
-        }
-        if self.key_down(VirtualKeyCode::S) {
-            move_direction -= Vector3::new(0.0, 1.0, 0.0).cross(right);
-        }
 
-        if self.key_down(VirtualKeyCode::A) {
-            move_direction -= right
-        }
-        if self.key_down(VirtualKeyCode::D) {
-            move_direction += right
-        }
+    //
+    // Events
+    //
 
-        if self.key_down(VirtualKeyCode::LShift) {
-            speed *= 2.0;
+    /// React to input from any device, returning the actions that were pressed or released
+    pub fn handle_input(&mut self, event: InputEvent) -> Vec<ActionEvent> {
+        // Presses while paused belong to the menus, but releases still have to reach the input state
+        if self.paused {
+            match event {
+                InputEvent::Pressed(..) | InputEvent::MouseMotion(..) => return Vec::new(),
+                _ => ()
+            }
         }
 
-        if move_direction.dot(move_direction) > 0.0 {
-            self.camera.position += speed * dt * move_direction.normal();
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.input(event);
         }
 
-        self.velocity.y -= dt * 8.0;
-        self.camera.position += dt * self.velocity;
-    }
-
-    fn update_particles(&mut self, dt: f64) {
-        let mut i = 0;
-        while i < self.particles.len() {
-            let remove = {
-                let particle = &mut self.particles[i];
-                particle.position += dt * particle.velocity;
+        match event {
+            InputEvent::MouseMotion(dx, dy) => {
+                let sensitivity = self.mouse_sensitivity;
+                let dy = if self.invert_y { -dy } else { dy };
 
-                particle.velocity -= dt * 3.5 * particle.velocity;
+                let (x, y) = (-dx * sensitivity, -dy * sensitivity);
 
-                particle.size -= 0.125 * dt;
-                if particle.size < 0.00 {
-                    true
+                if self.mouse_smoothing {
+                    self.pending_look.0 += x;
+                    self.pending_look.1 += y;
                 } else {
-                    false
+                    self.controlled_camera().rotate(x, y);
                 }
-            };
 
-            if remove {
-                self.particles.remove(i);
-            } else {
-                i += 1;
+                Vec::new()
+            }
+
+            event => {
+                let actions = self.input.handle(event);
+
+                for &action in actions.iter() {
+                    match action {
+                        ActionEvent::Pressed(action) => self.action_pressed(action),
+                        ActionEvent::Released(action) => self.action_released(action),
+                    }
+                }
+
+                actions
             }
         }
     }
 
-    fn check_collisions(&mut self) {
-        self.grounded = false;
 
-        for collider in self.world.get_colliders() {
-            let hull = self.get_hull();
+    /// The actions bound to an input, without changing the input state
+    pub fn bound_actions(&self, binding: Binding) -> Vec<Action> {
+        self.input.bound_actions(binding)
+    }
 
-            if let Some(resolve) = hull.overlap(collider) {
-                self.camera.position += resolve;
 
+    pub fn size_changed(&mut self, width: u32, height: u32) {
+        self.orthographic = Projection::Orthographic {
+            left: 0.0,
+            right: width as f64,
+            top: 0.0,
+            bottom: height as f64,
+            near: -1.0,
+            far: 1.0
+        };
 
-                if resolve.y * self.velocity.y < 0.0 {
-                    self.velocity.y = 0.0;
+        self.screen_width = width as f64;
+        self.screen_height = height as f64;
 
-                    if resolve.y > 0.0 {
-                        self.grounded = true;
-                    }
+        self.crosshair.x = width as f64 / 2.0;
+        self.crosshair.y = height as f64 / 2.0;
+    }
+
+
+    fn action_pressed(&mut self, action: Action) {
+        match action {
+            Action::Jump => {
+                if self.grounded && !self.health.dead() && self.camera_mode.walking() {
+                    self.velocity.y += 4.5 * self.size.sqrt();
                 }
             }
-        }
-    }
 
+            Action::Explore => {
+                self.explore();
+            }
 
-    fn get_hull(&self) -> BoundingBox {
-        let width = 0.4 * self.size;
-        let height = 1.8 * self.size;
+            Action::Fire if !self.health.dead() => self.arsenal.set_trigger(true),
 
-        BoundingBox {
-            min: self.camera.position - Vector3::new(width, height * 5.0 / 6.0, width),
-            max: self.camera.position + Vector3::new(width, height / 6.0, width),
-            color: Some(Color::new(1.0, 0.0, 0.0, 1.0))
-        }
-    }
+            Action::Weapon1 => self.arsenal.select(0),
+            Action::Weapon2 => self.arsenal.select(1),
+            Action::Weapon3 => self.arsenal.select(2),
 
+            Action::Reload => self.arsenal.reload(),
 
-    //
-    // Rendering
-    //
+            Action::PreviousItem => self.inventory.select_previous(),
+            Action::NextItem => self.inventory.select_next(),
+            Action::UseItem => self.use_item(),
+            Action::DropItem => self.drop_item(),
 
-    pub fn draw(&mut self, frame: &mut Frame) {
-        frame.clear(Color::new(0.01, 0.01, 0.01, 1.0));
+            Action::Respawn => {
+                let respawn_point = self.respawn_point;
+                self.teleport(respawn_point);
+            }
 
-        frame.set_projection(self.perspective);
-        frame.set_view(self.camera.view());
+            Action::CycleCamera => {
+                let mode = self.camera_mode.next();
+                self.set_camera_mode(mode);
+            }
 
-        self.draw_scene(frame);
+            Action::ReloadBindings => self.reload_bindings(),
 
-        self.draw_ui(frame);
-    }
+            Action::ToggleMap => self.map_open = !self.map_open,
+
+            Action::MapZoomIn => self.zoom_map(MAP_ZOOM_STEP),
+            Action::MapZoomOut => self.zoom_map(1.0 / MAP_ZOOM_STEP),
 
+            Action::TogglePause => {
+                let paused = self.scheduler.paused();
+                self.scheduler.set_paused(!paused);
+            }
 
-    fn draw_scene(&mut self, frame: &mut Frame) {
-        // All objects to draw
-        let mut drawables: Vec<&Draw> = Vec::new();
+            Action::SingleStep => self.scheduler.single_step(),
 
-        // Add the boxes
-        for b in self.boxes.iter() {
-            drawables.push(b);
+            _ => ()
         }
+    }
 
-        // Draw the world
-        frame.draw(&self.world);
 
-        for particle in self.particles.iter() {
-            frame.draw(particle);
+    fn action_released(&mut self, action: Action) {
+        if action == Action::Fire {
+            self.arsenal.set_trigger(false);
         }
+    }
 
-        // Draw all objects
-        for drawable in drawables.into_iter() {
-            frame.draw(drawable);
-        }
+    fn action_down(&self, action: Action) -> bool {
+        self.input.action_down(action)
     }
 
 
-    fn draw_ui(&mut self, frame: &mut Frame) {
-        frame.set_projection(self.orthographic);
-        frame.set_view(View::None);
+    fn zoom_map(&mut self, factor: f64) {
+        let (min, max) = MAP_SCALE_RANGE;
+        self.map_scale = (self.map_scale * factor).clamp(min, max);
+    }
 
-        frame.clear_depth();
-        frame.draw(&self.crosshair);
 
-        // self.draw_minimap(frame, 300, 300);
+    fn reload_bindings(&mut self) {
+        match Bindings::load(BINDINGS_PATH) {
+            Ok(bindings) => self.set_bindings(bindings),
+            Err(e) => println!("Failed to reload bindings: {}", e),
+        }
     }
 
 
-    /*fn draw_minimap(&mut self, pass: &mut Frame, width: u32, height: u32) {
-        let distance = 100.0;
+    //
+    // Console
+    //
+
+    /// Run a line typed into the console, printing it and its output to the console
+    pub fn run_command(&mut self, line: &str) -> Result<String, String> {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.command(line);
+        }
 
-        pass.set_viewport(Some(Rect {
-            left: 0,
-            bottom: 0,
-            width,
-            height
-        }));
+        self.console.remember(line);
+        self.console.print(&format!("> {}", line));
 
-        pass.clear(Color::new(0.0, 0.0, 0.0, 1.0));
+        let result = console::execute(self, line);
 
-        pass.set_projection(Projection::Perspective {
-            fov: 70.0,
-            aspect: width as f64 / height as f64,
-            near: 0.01,
-            far: distance + 10.0,
-        });
+        match result {
+            Ok(ref output) => self.console.print(output),
+            Err(ref e) => self.console.print(e),
+        }
 
-        let up = Vector3::new(0.0, 1.0, 0.0);
+        result
+    }
 
-        pass.set_view(View::LookAt {
-            eye: Vector3::new(self.camera.position.x, distance, self.camera.position.z),
-            target: self.camera.position,
-            up: up.cross(self.camera.direction()).cross(up)
-        });
+    /// Run every command in a file
+    pub fn run_script(&mut self, path: &str) -> Result<String, String> {
+        console::run_script(self, path)
+    }
+
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
 
-        self.draw_scene(pass);
 
-        pass.draw(&self.get_hull());
-    }*/
+    fn teleport(&mut self, position: Vector3) {
+        self.camera.position = position;
+        self.previous_camera = self.camera;
+
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+    }
 
 
     //
-    // Events
+    // Weapons
     //
 
-    pub fn handle_event(&mut self, event: Event) {
-        match event {
-            Event::WindowEvent { event, .. } => {
-                self.handle_window_event(event);
-            }
-            Event::DeviceEvent { event, .. } => {
-                self.handle_device_event(event);
-            }
+    fn fire(&mut self, weapon: &'static WeaponDef) {
+        let origin = self.camera.position;
+        let aim = self.camera.direction();
 
-            _ => ()
-        }
-    }
+        self.effects.add_trauma(weapon.shake);
 
+        self.lighting.flash(PointLight {
+            position: origin + MUZZLE_FLASH_DISTANCE * aim,
+            color: MUZZLE_FLASH_COLOR,
+            radius: MUZZLE_FLASH_RADIUS,
+        });
 
-    fn handle_window_event(&mut self, event: WindowEvent) {
-        match event {
-            WindowEvent::CloseRequested => { self.close(); }
-
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state, virtual_keycode: Some(virtual_keycode), ..
-                }, ..
-            } => {
-                match state {
-                    ElementState::Pressed => {
-                        if self.pressed_keys.insert(virtual_keycode) {
-                            self.key_pressed(virtual_keycode);
-                        }
-                    }
-                    ElementState::Released => {
-                        self.pressed_keys.remove(&virtual_keycode);
-                        self.key_released(virtual_keycode);
-                    }
-                }
+        match weapon.mode {
+            FireMode::HitScan => {
+                let direction = weapons::scatter(aim, weapon.spread, &mut self.rng);
+                self.shoot_ray(origin, direction, weapon.damage, 100);
             }
 
-            WindowEvent::MouseInput {
-                state, button, ..
-            } => {
-                match state {
-                    ElementState::Pressed => {
-                        self.mouse_pressed(button);
-                    }
-
-                    ElementState::Released => {
-                        self.mouse_released(button);
-                    }
+            FireMode::Spread { pellets } => {
+                for _ in 0..pellets {
+                    let direction = weapons::scatter(aim, weapon.spread, &mut self.rng);
+                    self.shoot_ray(origin, direction, weapon.damage, 30);
                 }
             }
 
-            WindowEvent::Resized(w, h) => {
-                self.size_changed(w, h);
-            }
+            FireMode::Projectile { speed, radius } => {
+                let direction = weapons::scatter(aim, weapon.spread, &mut self.rng);
 
-            _ => ()
+                self.projectiles.push(Projectile {
+                    position: origin,
+                    velocity: speed * direction,
+                    damage: weapon.damage,
+                    radius,
+                    lifetime: 10.0,
+                });
+            }
         }
     }
 
-    fn handle_device_event(&mut self, event: DeviceEvent) {
-        match event {
-            DeviceEvent::MouseMotion { delta: (dx, dy) } => {
-                let sensitivity = 0.001;
-                self.camera.rotate(-dx * sensitivity, -dy * sensitivity);
-            }
 
-            _ => ()
+    /// Damage the first prop or wall along a ray
+    fn shoot_ray(&mut self, origin: Vector3, direction: Vector3, damage: f64, sparks: u32) {
+        let wall = self.world.hit_scan(origin, direction);
+        let prop = self.physics.hit_scan(origin, direction);
+
+        if let Some((distance, normal, index)) = self.enemy_hit_scan(origin, direction) {
+            let enemy_closer = wall.as_ref().is_none_or(|wall| { distance < wall.distance }) &&
+                prop.is_none_or(|prop| { distance < prop.0 });
+
+            if enemy_closer {
+                self.damage_enemy(index, damage);
+                self.spawn_sparks(origin + distance * direction, direction.reflect(normal), 0.4, sparks);
+                return;
+            }
         }
-    }
 
+        let prop_closer = match (&wall, &prop) {
+            (Some(wall), Some((distance, _, _))) => distance < &wall.distance,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+
+        if prop_closer {
+            if let Some((distance, normal, index)) = prop {
+                let hit = origin + distance * direction;
 
-    fn close(&mut self) {
-        self.running = false;
+                if let Some(position) = self.physics.damage(index, damage, direction) {
+                    self.spawn_sparks(position, Vector3::new(0.0, 1.0, 0.0), 2.0, 400);
+                }
+
+                self.spawn_sparks(hit, direction.reflect(normal), 0.4, sparks);
+            }
+        } else if let Some(wall) = wall {
+            self.world.damage(&wall, damage);
+            self.spawn_sparks(wall.point, direction.reflect(wall.normal), 0.4, sparks);
+        }
     }
 
 
-    fn size_changed(&mut self, width: u32, height: u32) {
-        self.perspective = Projection::Perspective {
-            fov: 70.0,
-            aspect: width as f64 / height as f64,
-            near: 0.01,
-            far: 100.0,
-        };
+    fn update_projectiles(&mut self, dt: f64) {
+        let mut i = 0;
+        while i < self.projectiles.len() {
+            let (position, velocity) = {
+                let projectile = &mut self.projectiles[i];
+                projectile.velocity.y -= 0.25 * dt * GRAVITY;
+                projectile.lifetime -= dt;
+                (projectile.position, projectile.velocity)
+            };
 
-        self.orthographic = Projection::Orthographic {
-            left: 0.0,
-            right: width as f64,
-            top: 0.0,
-            bottom: height as f64,
-            near: -1.0,
-            far: 1.0
-        };
+            // Trace the path travelled during this step, the hit distance is measured in seconds
+            let wall = self.world.hit_scan(position, velocity)
+                .and_then(|hit| { if hit.distance < dt { Some(hit) } else { None } });
 
-        self.crosshair.x = width as f64 / 2.0;
-        self.crosshair.y = height as f64 / 2.0;
-    }
+            // Enemies stop projectiles the same way props do
+            let prop = match (self.physics.hit_scan(position, velocity), self.enemy_hit_scan(position, velocity)) {
+                (Some(prop), Some(enemy)) => Some(if prop.0 < enemy.0 { prop } else { enemy }),
+                (prop, enemy) => prop.or(enemy),
+            }.and_then(|hit| { if hit.0 < dt { Some(hit) } else { None } });
 
+            if wall.is_some() || prop.is_some() || self.projectiles[i].lifetime < 0.0 {
+                let projectile = self.projectiles.remove(i);
 
-    fn key_pressed(&mut self, key: VirtualKeyCode) {
-        match key {
-            VirtualKeyCode::Escape => self.close(),
+                let prop_closer = match (&wall, &prop) {
+                    (Some(wall), Some((distance, _, _))) => distance < &wall.distance,
+                    (None, Some(_)) => true,
+                    _ => false,
+                };
 
-            VirtualKeyCode::Space => {
-                if self.grounded {
-                    self.velocity.y += 4.5 * self.size.sqrt();
-                }
-            }
+                let (center, normal) = if prop_closer {
+                    let (distance, normal, _) = prop.unwrap();
+                    (position + distance * velocity, normal)
+                } else if let Some(ref wall) = wall {
+                    (wall.point, wall.normal)
+                } else {
+                    (position, Vector3::new(0.0, 1.0, 0.0))
+                };
 
-            VirtualKeyCode::Tab => {
-                self.world.explore(self.camera.position);
-            }
+                if let Some(wall) = wall {
+                    if !prop_closer {
+                        self.world.damage(&wall, projectile.damage);
+                    }
+                }
 
-            VirtualKeyCode::R => {
-                self.camera.position.x = 0.0;
-                self.camera.position.y = self.get_hull().size().y;
-                self.camera.position.z = 0.0;
+                self.explode(center, normal, projectile.radius, projectile.damage);
+            } else {
+                self.projectiles[i].position += dt * velocity;
+                i += 1;
             }
-
-            _ => ()
         }
     }
 
 
-    #[allow(unused_variables)]
-    fn key_released(&mut self, key: VirtualKeyCode) {}
+    fn explode(&mut self, center: Vector3, normal: Vector3, radius: f64, damage: f64) {
+        let offset = self.camera.position - center;
+        let distance = offset.dot(offset).sqrt();
+        if distance < radius {
+            self.health.damage(damage * (1.0 - distance / radius));
+        }
 
-    fn key_down(&self, key: VirtualKeyCode) -> bool {
-        self.pressed_keys.contains(&key)
-    }
+        for position in self.physics.explode(center, radius, damage) {
+            self.spawn_sparks(position, Vector3::new(0.0, 1.0, 0.0), 2.0, 200);
+        }
 
-    #[allow(unused_variables)]
-    fn mouse_pressed(&mut self, button: MouseButton) {
-        let result = self.world.get_colliders()
-            .filter_map(|c|{
-                c.hit_scan(self.camera.position, self.camera.direction())
-            }).min_by(|a, b| {
-            a.0.partial_cmp(&b.0).unwrap()
-        });
+        let mut i = 0;
+        while i < self.enemies.len() {
+            let offset = self.enemies[i].hull().center() - center;
+            let distance = offset.dot(offset).sqrt();
+            let count = self.enemies.len();
 
-        if let Some((distance, normal)) = result {
-            let hit = self.camera.position + distance * self.camera.direction();
-            let reflection = self.camera.direction().reflect(normal);
+            if distance < radius {
+                self.damage_enemy(i, damage * (1.0 - distance / radius));
+            }
 
+            // Killed enemies are removed, leaving the next one at the same index
+            if self.enemies.len() == count {
+                i += 1;
+            }
+        }
 
-            use rand::{
-                thread_rng,
-                Rng
-            };
+        self.spawn_sparks(center, normal, 3.0, 400);
+    }
 
-            let mut rng = thread_rng();
 
-            let perp_x = reflection.cross(Vector3::new(
-                rng.gen_range(0.01, 1.0),
-                rng.gen_range(0.01, 1.0),
-                rng.gen_range(0.01, 1.0)
-            )).normal();
+    /// Spawn a burst of particles in a cone around a direction
+    fn spawn_sparks(&mut self, position: Vector3, direction: Vector3, spread: f64, count: u32) {
+        use rand::Rng;
 
-            let perp_y = perp_x.cross(reflection);
+        let rng = &mut self.rng;
 
-            for _ in 0..400 {
-                let theta = rng.gen_range(0.0, 2.0 * PI);
+        let perp_x = direction.cross(Vector3::new(
+            rng.gen_range(0.01, 1.0),
+            rng.gen_range(0.01, 1.0),
+            rng.gen_range(0.01, 1.0)
+        )).normal();
 
-                let r = rng.gen_range(0.0, 0.4);
-                let (dy, dx) = theta.sin_cos();
+        let perp_y = perp_x.cross(direction);
 
-                let particle = Particle {
-                    position: hit,
-                    velocity: rng.gen_range(1.0, 8.0) * (reflection + r * dx * perp_x + r * dy * perp_y).normal(),
-                    size: rng.gen_range(0.05, 0.1),
-                };
+        for _ in 0..count {
+            let theta = rng.gen_range(0.0, 2.0 * PI);
 
-                self.particles.push(particle);
-            }
-        }
-    }
+            let r = rng.gen_range(0.0, spread);
+            let (dy, dx) = theta.sin_cos();
 
-    #[allow(unused_variables)]
-    fn mouse_released(&mut self, button: MouseButton) {
+            let particle = Particle {
+                position,
+                previous_position: position,
+                velocity: rng.gen_range(1.0, 8.0) * (direction + r * dx * perp_x + r * dy * perp_y).normal(),
+                size: rng.gen_range(0.05, 0.1),
+            };
 
+            self.particles.push(particle);
+        }
     }
 }
 
@@ -581,12 +1962,52 @@ impl Draw for Crosshair {
 
 struct Particle {
     position: Vector3,
+    previous_position: Vector3,
     velocity: Vector3,
     size: f64
 }
 
-impl Draw for Particle {
-    fn draw(&self) -> DrawCommand {
-        BoundingBox::cube(self.position, self.size).draw()
+impl Particle {
+    /// The particle as it should be drawn between its last two positions
+    fn interpolate(&self, alpha: f64) -> BoundingBox {
+        let position = self.previous_position + alpha * (self.position - self.previous_position);
+        BoundingBox::cube(position, self.size)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn draw_distance_keeps_the_fog_start() {
+        let mut game = GameState::with_seed(1);
+
+        game.set_fog_start(10.0);
+        game.set_draw_distance(200.0);
+        assert_eq!(game.fog_start(), 10.0);
+
+        // Fog can't start past where it ends
+        game.set_draw_distance(5.0);
+        assert_eq!(game.fog_start(), 5.0);
+    }
+
+    #[test]
+    fn enemies_are_pushed_out_of_walls() {
+        let mut game = GameState::with_seed(1);
+
+        let wall = game.world.get_colliders()
+            .find(|collider| { collider.size().y > 2.0 })
+            .copied()
+            .unwrap();
+
+        let center = wall.center();
+        game.enemies.push(Enemy::new(Vector3::new(center.x, 0.0, center.z)));
+        game.update_enemies(0.01);
+
+        assert!(!game.enemies[0].hull().intersect(&wall));
     }
 }
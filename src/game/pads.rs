@@ -0,0 +1,71 @@
+use graphics_3d::{
+    trap::{
+        Vector2,
+    },
+    Color
+};
+
+use bounding_box::{
+    BoundingBox,
+    Rectangle,
+};
+
+
+/// Damage per second dealt by spikes
+pub const SPIKE_DAMAGE: f64 = 25.0;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PadKind {
+    /// Hurts whoever stands on it
+    Spikes,
+
+    /// Sets the respawn point when stepped on
+    Checkpoint,
+}
+
+
+/// A flat trigger area lying on the floor of a room
+pub struct Pad {
+    pub kind: PadKind,
+    pub area: Rectangle,
+
+    pub active: bool,
+}
+
+
+impl Pad {
+    pub fn new(kind: PadKind, center: Vector2) -> Pad {
+        let size = match kind {
+            PadKind::Spikes => Vector2::new(2.0, 2.0),
+            PadKind::Checkpoint => Vector2::new(1.5, 1.5),
+        };
+
+        Pad {
+            kind,
+            area: Rectangle::centered(center, size),
+
+            active: false,
+        }
+    }
+
+
+    /// Is a hull standing on this pad
+    pub fn touches(&self, hull: &BoundingBox) -> bool {
+        hull.min.y < 0.1 && self.area.intersects(&hull.project_y())
+    }
+
+
+    /// The pad as it is drawn, a thin slab on the floor
+    pub fn hull(&self) -> BoundingBox {
+        let mut b = self.area.extrude_y(0.0, 0.05);
+
+        b.color = Some(match self.kind {
+            PadKind::Spikes => Color::new(0.6, 0.05, 0.05, 1.0),
+            PadKind::Checkpoint if self.active => Color::new(0.2, 0.6, 1.0, 1.0),
+            PadKind::Checkpoint => Color::new(0.1, 0.2, 0.35, 1.0),
+        });
+
+        b
+    }
+}
@@ -0,0 +1,310 @@
+use graphics_3d::{
+    trap::{
+        Vector3
+    },
+    Draw,
+    DrawCommand,
+    Color
+};
+
+use bounding_box::BoundingBox;
+
+use super::world::World;
+
+
+/// Downwards acceleration shared by the player and all bodies
+pub const GRAVITY: f64 = 8.0;
+
+/// How quickly sliding bodies come to rest on the ground
+const FRICTION: f64 = 6.0;
+
+/// Velocity change per point of damage for a body of unit mass
+const DAMAGE_IMPULSE: f64 = 0.5;
+
+/// Bounces slower than this are absorbed completely
+const REST_SPEED: f64 = 0.5;
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PropKind {
+    Crate,
+    Barrel,
+}
+
+impl PropKind {
+    fn half_size(&self) -> Vector3 {
+        match *self {
+            PropKind::Crate => Vector3::new(0.4, 0.4, 0.4),
+            PropKind::Barrel => Vector3::new(0.3, 0.45, 0.3),
+        }
+    }
+
+    fn mass(&self) -> f64 {
+        match *self {
+            PropKind::Crate => 20.0,
+            PropKind::Barrel => 35.0,
+        }
+    }
+
+    fn restitution(&self) -> f64 {
+        match *self {
+            PropKind::Crate => 0.2,
+            PropKind::Barrel => 0.35,
+        }
+    }
+
+    fn health(&self) -> f64 {
+        match *self {
+            PropKind::Crate => 60.0,
+            PropKind::Barrel => 40.0,
+        }
+    }
+
+    fn color(&self) -> Color {
+        match *self {
+            PropKind::Crate => Color::new(0.55, 0.35, 0.15, 1.0),
+            PropKind::Barrel => Color::new(0.35, 0.2, 0.1, 1.0),
+        }
+    }
+}
+
+
+
+/// A box that moves under gravity and collides with the world and other bodies
+pub struct RigidBody {
+    pub kind: PropKind,
+
+    pub position: Vector3,
+    pub velocity: Vector3,
+
+    half_size: Vector3,
+    mass: f64,
+    restitution: f64,
+
+    health: f64,
+
+    grounded: bool,
+}
+
+
+impl RigidBody {
+    pub fn new(kind: PropKind, position: Vector3) -> RigidBody {
+        RigidBody {
+            kind,
+
+            position,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+
+            half_size: kind.half_size(),
+            mass: kind.mass(),
+            restitution: kind.restitution(),
+
+            health: kind.health(),
+
+            grounded: false,
+        }
+    }
+
+
+    pub fn hull(&self) -> BoundingBox {
+        BoundingBox {
+            min: self.position - self.half_size,
+            max: self.position + self.half_size,
+            color: Some(self.kind.color()),
+        }
+    }
+
+
+    /// Apply a correction along a single axis and bounce off the surface
+    fn resolve(&mut self, resolve: Vector3) {
+        self.position += resolve;
+
+        macro_rules! bounce {
+            ($d:ident) => {
+                if resolve.$d * self.velocity.$d < 0.0 {
+                    self.velocity.$d *= -self.restitution;
+
+                    if self.velocity.$d.abs() < REST_SPEED {
+                        self.velocity.$d = 0.0;
+                    }
+                }
+            };
+        }
+
+        bounce!(x);
+        bounce!(y);
+        bounce!(z);
+
+        if resolve.y > 0.0 {
+            self.grounded = true;
+        }
+    }
+}
+
+
+impl Draw for RigidBody {
+    fn draw(&self) -> DrawCommand {
+        self.hull().draw()
+    }
+}
+
+
+
+/// All dynamic bodies in the world
+pub struct Physics {
+    bodies: Vec<RigidBody>
+}
+
+
+impl Physics {
+    pub fn new() -> Physics {
+        Physics {
+            bodies: Vec::new()
+        }
+    }
+
+
+    pub fn spawn(&mut self, kind: PropKind, position: Vector3) {
+        self.bodies.push(RigidBody::new(kind, position));
+    }
+
+    pub fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+
+
+    /// Advance all bodies by one fixed time step
+    pub fn step(&mut self, dt: f64, world: &World) {
+        for body in self.bodies.iter_mut() {
+            body.velocity.y -= dt * GRAVITY;
+
+            if body.grounded {
+                body.velocity.x -= dt * FRICTION * body.velocity.x;
+                body.velocity.z -= dt * FRICTION * body.velocity.z;
+            }
+
+            body.position += dt * body.velocity;
+            body.grounded = false;
+
+            for collider in world.get_colliders() {
+                if let Some(resolve) = body.hull().overlap(collider) {
+                    body.resolve(resolve);
+                }
+            }
+        }
+
+        self.collide_bodies();
+    }
+
+
+    /// Separate overlapping bodies, distributing the correction by mass
+    fn collide_bodies(&mut self) {
+        for j in 1..self.bodies.len() {
+            let (left, right) = self.bodies.split_at_mut(j);
+            let b = &mut right[0];
+
+            for a in left.iter_mut() {
+                if let Some(resolve) = a.hull().overlap(&b.hull()) {
+                    let inverse_a = 1.0 / a.mass;
+                    let inverse_b = 1.0 / b.mass;
+                    let total = inverse_a + inverse_b;
+
+                    a.position += (inverse_a / total) * resolve;
+                    b.position -= (inverse_b / total) * resolve;
+
+                    let normal = resolve.normal();
+                    let approach = (a.velocity - b.velocity).dot(normal);
+
+                    if approach < 0.0 {
+                        let restitution = a.restitution.min(b.restitution);
+                        let impulse = -(1.0 + restitution) * approach / total;
+
+                        a.velocity += (impulse * inverse_a) * normal;
+                        b.velocity -= (impulse * inverse_b) * normal;
+                    }
+
+                    if resolve.y > 0.0 {
+                        a.grounded = true;
+                    } else if resolve.y < 0.0 {
+                        b.grounded = true;
+                    }
+                }
+            }
+        }
+    }
+
+
+    /// Find the closest body hit by a ray, returning the distance, the normal and the body's index
+    pub fn hit_scan(&self, origin: Vector3, direction: Vector3) -> Option<(f64, Vector3, usize)> {
+        self.bodies.iter().enumerate()
+            .filter_map(|(i, body)| {
+                body.hull().hit_scan(origin, direction).map(|(distance, normal)| { (distance, normal, i) })
+            }).min_by(|a, b| {
+            a.0.partial_cmp(&b.0).unwrap()
+        })
+    }
+
+
+    /// Damage a body, knocking it along a direction.
+    /// Returns the position of the body if it was destroyed.
+    pub fn damage(&mut self, index: usize, amount: f64, direction: Vector3) -> Option<Vector3> {
+        {
+            let body = &mut self.bodies[index];
+
+            body.velocity += (DAMAGE_IMPULSE * amount / body.mass) * direction;
+            body.health -= amount;
+
+            if body.health > 0.0 {
+                return None;
+            }
+        }
+
+        Some(self.bodies.remove(index).position)
+    }
+
+
+    /// Damage all bodies within a radius, falling off with distance.
+    /// Returns the positions of the destroyed bodies.
+    pub fn explode(&mut self, center: Vector3, radius: f64, damage: f64) -> Vec<Vector3> {
+        let mut destroyed = Vec::new();
+
+        for i in (0..self.bodies.len()).rev() {
+            let offset = self.bodies[i].position - center;
+            let distance = offset.dot(offset).sqrt();
+
+            if distance < radius {
+                let direction = if distance > 0.0 { offset.normal() } else { Vector3::new(0.0, 1.0, 0.0) };
+
+                if let Some(position) = self.damage(i, damage * (1.0 - distance / radius), direction) {
+                    destroyed.push(position);
+                }
+            }
+        }
+
+        destroyed
+    }
+
+
+    /// Push bodies out of the way of a moving hull with the given mass.
+    /// Returns the corrections that should be applied to the hull itself.
+    pub fn push(&mut self, hull: &BoundingBox, mass: f64) -> Vec<Vector3> {
+        let mut corrections = Vec::new();
+
+        for body in self.bodies.iter_mut() {
+            if let Some(resolve) = hull.overlap(&body.hull()) {
+                if resolve.y != 0.0 {
+                    // Standing on top of, or bumping into the bottom of a body
+                    corrections.push(resolve);
+                } else {
+                    let share = mass / (mass + body.mass);
+
+                    body.position -= share * resolve;
+                    corrections.push((1.0 - share) * resolve);
+                }
+            }
+        }
+
+        corrections
+    }
+}
@@ -0,0 +1,821 @@
+use graphics_3d::{
+    Frame,
+
+    glutin::{
+        Event,
+        WindowEvent,
+        VirtualKeyCode,
+    },
+};
+
+use input::{
+    InputEvent,
+    ActionEvent,
+    Action,
+    Binding,
+};
+
+use super::{
+    GameState,
+    RESPAWN_DELAY,
+
+    hud::{
+        Bar,
+        Overlay,
+        MenuPanel,
+        Panel,
+    },
+
+    text::{
+        Text,
+        text_width,
+        GLYPH_WIDTH,
+        GLYPH_HEIGHT,
+    },
+};
+
+
+/// Smallest and largest mouse sensitivity selectable in the options
+pub const SENSITIVITY_RANGE: (f64, f64) = (0.0002, 0.004);
+const SENSITIVITY_STEP: f64 = 0.0002;
+
+/// Smallest and largest field of view selectable in the options
+const FIELD_OF_VIEW_RANGE: (f64, f64) = (50.0, 110.0);
+const FIELD_OF_VIEW_STEP: f64 = 5.0;
+
+/// Shortest and longest draw distance selectable in the options
+pub const DRAW_DISTANCE_RANGE: (f64, f64) = (20.0, 300.0);
+const DRAW_DISTANCE_STEP: f64 = 10.0;
+
+/// Size of the text in the console and the space between its lines
+const CONSOLE_TEXT_SCALE: f64 = 2.0;
+const CONSOLE_LINE_SPACING: f64 = 4.0;
+
+
+
+/// What the stack should do after a state has handled something
+enum Transition {
+    None,
+
+    Push(Box<dyn State>),
+    Pop,
+    Replace(Box<dyn State>),
+
+    /// Abandon the current game and return to the main menu
+    MainMenu,
+    Quit,
+}
+
+
+/// One screen of the game, only the state on top of the stack is updated, drawn and receives input
+trait State {
+    fn update(&mut self, _game: &mut GameState, _dt: f64) -> Transition {
+        Transition::None
+    }
+
+    fn draw(&self, game: &mut GameState, frame: &mut Frame);
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition;
+
+    /// Receive typed text
+    fn handle_char(&mut self, _game: &mut GameState, _c: char) -> Transition {
+        Transition::None
+    }
+
+    /// Whether the cursor should be captured for looking around
+    fn grab_cursor(&self) -> bool {
+        false
+    }
+
+    /// Whether the simulation is frozen while this state is on top
+    fn pauses_game(&self) -> bool {
+        true
+    }
+}
+
+
+
+/// The game together with the screens shown on top of it
+pub struct StateStack {
+    game: GameState,
+    states: Vec<Box<dyn State>>,
+
+    running: bool,
+}
+
+
+impl StateStack {
+    /// Start at the main menu, with the game frozen behind it
+    pub fn new(game: GameState) -> StateStack {
+        Self::with_state(game, Box::new(MainMenu::new()))
+    }
+
+    /// Start playing straight away
+    pub fn playing(game: GameState) -> StateStack {
+        Self::with_state(game, Box::new(Playing))
+    }
+
+    fn with_state(game: GameState, state: Box<dyn State>) -> StateStack {
+        let mut stack = StateStack {
+            game,
+            states: vec![state],
+
+            running: true,
+        };
+
+        stack.apply(Transition::None);
+
+        stack
+    }
+
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    pub fn game_mut(&mut self) -> &mut GameState {
+        &mut self.game
+    }
+
+    pub fn grab_cursor(&self) -> bool {
+        self.top().grab_cursor()
+    }
+
+
+    pub fn handle_event(&mut self, event: Event) {
+        let input = match event {
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                self.running = false;
+                None
+            }
+
+            Event::WindowEvent { event: WindowEvent::Resized(w, h), .. } => {
+                self.game.size_changed(w, h);
+                None
+            }
+
+            Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. } => {
+                let transition = self.states.last_mut().unwrap().handle_char(&mut self.game, c);
+                self.apply(transition);
+                None
+            }
+
+            Event::WindowEvent { ref event, .. } => InputEvent::from_window_event(event),
+            Event::DeviceEvent { ref event, .. } => InputEvent::from_device_event(event),
+
+            _ => None
+        };
+
+        if let Some(input) = input {
+            self.handle_input(input);
+        }
+    }
+
+    pub fn handle_input(&mut self, event: InputEvent) {
+        let transition = self.states.last_mut().unwrap().handle_input(&mut self.game, event);
+        self.apply(transition);
+    }
+
+
+    pub fn update(&mut self, dt: f64) {
+        let transition = self.states.last_mut().unwrap().update(&mut self.game, dt);
+        self.apply(transition);
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        self.states.last().unwrap().draw(&mut self.game, frame);
+    }
+
+
+    fn top(&self) -> &dyn State {
+        &**self.states.last().unwrap()
+    }
+
+    fn apply(&mut self, transition: Transition) {
+        match transition {
+            Transition::None => (),
+
+            Transition::Push(state) => self.states.push(state),
+
+            Transition::Pop => {
+                // The bottom state has nowhere to return to
+                if self.states.len() > 1 {
+                    self.states.pop();
+                }
+            }
+
+            Transition::Replace(state) => {
+                self.states.pop();
+                self.states.push(state);
+            }
+
+            Transition::MainMenu => {
+                self.game.stop_recording();
+
+                // Start a fresh game behind the menu, keeping the window size and options
+                let mut game = GameState::new();
+
+                let (width, height) = self.game.screen_size();
+                game.size_changed(width as u32, height as u32);
+                game.set_mouse_sensitivity(self.game.mouse_sensitivity());
+                game.set_field_of_view(self.game.field_of_view());
+                game.set_invert_y(self.game.invert_y());
+                game.set_mouse_smoothing(self.game.mouse_smoothing());
+                game.set_draw_distance(self.game.draw_distance());
+                game.set_fog_mode(self.game.fog_mode());
+                game.set_fog_start(self.game.fog_start());
+
+                self.game = game;
+                self.states = vec![Box::new(MainMenu::new())];
+            }
+
+            Transition::Quit => self.running = false,
+        }
+
+        let paused = self.top().pauses_game();
+        self.game.set_paused(paused);
+    }
+}
+
+
+
+struct Playing;
+
+
+impl State for Playing {
+    fn update(&mut self, game: &mut GameState, dt: f64) -> Transition {
+        game.update(dt);
+
+        if game.dead() {
+            Transition::Push(Box::new(GameOver))
+        } else {
+            Transition::None
+        }
+    }
+
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        game.draw(frame);
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        play_input(game, event)
+    }
+
+    fn grab_cursor(&self) -> bool {
+        true
+    }
+
+    fn pauses_game(&self) -> bool {
+        false
+    }
+}
+
+
+/// Pass input to the game, opening the pause menu or the console when asked to
+fn play_input(game: &mut GameState, event: InputEvent) -> Transition {
+    let actions = game.handle_input(event);
+
+    if actions.contains(&ActionEvent::Pressed(Action::Pause)) {
+        Transition::Push(Box::new(Paused::new()))
+    } else if actions.contains(&ActionEvent::Pressed(Action::Console)) {
+        Transition::Push(Box::new(ConsoleScreen::new()))
+    } else {
+        Transition::None
+    }
+}
+
+
+
+/// Shown while the player is dead, the game keeps running until they respawn
+struct GameOver;
+
+
+impl State for GameOver {
+    fn update(&mut self, game: &mut GameState, dt: f64) -> Transition {
+        game.update(dt);
+
+        if game.dead() {
+            Transition::None
+        } else {
+            Transition::Pop
+        }
+    }
+
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        game.draw(frame);
+        game.begin_ui(frame);
+
+        let (width, height) = game.screen_size();
+
+        frame.draw(&Overlay {
+            width,
+            height,
+            color: [0.3, 0.0, 0.0, 0.6],
+        });
+
+        frame.clear_depth();
+
+        let title = "You died";
+        let scale = 4.0;
+
+        frame.draw(&Text {
+            x: (width - text_width(title, scale)) / 2.0,
+            y: height / 2.0 - 60.0,
+
+            text: title,
+            scale,
+            color: [0.9, 0.9, 0.9, 1.0],
+        });
+
+        let remaining = game.respawn_time().unwrap_or(0.0);
+
+        frame.draw(&Bar {
+            x: width / 2.0 - 150.0,
+            y: height / 2.0 + 4.0,
+            width: 300.0,
+            height: 8.0,
+            fill: 1.0 - remaining / RESPAWN_DELAY,
+            color: [0.7, 0.1, 0.1, 1.0],
+        });
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        play_input(game, event)
+    }
+
+    fn grab_cursor(&self) -> bool {
+        true
+    }
+
+    fn pauses_game(&self) -> bool {
+        false
+    }
+}
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Entry {
+    Play,
+    Resume,
+    Options,
+    MainMenu,
+    Quit,
+
+    Sensitivity,
+    InvertY,
+    Smoothing,
+    FieldOfView,
+    DrawDistance,
+    Fog,
+    Back,
+}
+
+
+impl Entry {
+    fn label(&self) -> &'static str {
+        match *self {
+            Entry::Play => "Play",
+            Entry::Resume => "Resume",
+            Entry::Options => "Options",
+            Entry::MainMenu => "Main menu",
+            Entry::Quit => "Quit",
+
+            Entry::Sensitivity => "Sensitivity",
+            Entry::InvertY => "Invert Y",
+            Entry::Smoothing => "Mouse smoothing",
+            Entry::FieldOfView => "Field of view",
+            Entry::DrawDistance => "Draw distance",
+            Entry::Fog => "Fog",
+            Entry::Back => "Back",
+        }
+    }
+
+    /// Where an adjustable setting lies in its range, from 0 to 1.
+    /// Switches are either empty or full.
+    fn value(&self, game: &GameState) -> Option<f64> {
+        let switch = |on: bool| { if on { 1.0 } else { 0.0 } };
+
+        let (value, (min, max)) = match *self {
+            Entry::Sensitivity => (game.mouse_sensitivity(), SENSITIVITY_RANGE),
+            Entry::InvertY => (switch(game.invert_y()), (0.0, 1.0)),
+            Entry::Smoothing => (switch(game.mouse_smoothing()), (0.0, 1.0)),
+            Entry::FieldOfView => (game.field_of_view(), FIELD_OF_VIEW_RANGE),
+            Entry::DrawDistance => (game.draw_distance(), DRAW_DISTANCE_RANGE),
+            Entry::Fog => (game.fog_mode().index() as f64, (0.0, 2.0)),
+
+            _ => return None
+        };
+
+        Some((value - min) / (max - min))
+    }
+
+    /// Change an adjustable setting by a number of steps, switches are flipped by any step
+    fn adjust(&self, game: &mut GameState, steps: f64) {
+        match *self {
+            Entry::InvertY => {
+                let invert_y = game.invert_y();
+                game.set_invert_y(!invert_y);
+            }
+
+            Entry::Smoothing => {
+                let smoothing = game.mouse_smoothing();
+                game.set_mouse_smoothing(!smoothing);
+            }
+
+            Entry::Sensitivity => {
+                let (min, max) = SENSITIVITY_RANGE;
+                let value = game.mouse_sensitivity() + steps * SENSITIVITY_STEP;
+                game.set_mouse_sensitivity(value.clamp(min, max));
+            }
+
+            Entry::FieldOfView => {
+                let (min, max) = FIELD_OF_VIEW_RANGE;
+                let value = game.field_of_view() + steps * FIELD_OF_VIEW_STEP;
+                game.set_field_of_view(value.clamp(min, max));
+            }
+
+            Entry::DrawDistance => {
+                let (min, max) = DRAW_DISTANCE_RANGE;
+                let value = game.draw_distance() + steps * DRAW_DISTANCE_STEP;
+                game.set_draw_distance(value.clamp(min, max));
+            }
+
+            Entry::Fog => {
+                let mode = game.fog_mode().next();
+                game.set_fog_mode(mode);
+            }
+
+            _ => ()
+        }
+    }
+}
+
+
+/// A list of entries moved through with the menu actions
+struct Menu {
+    title: &'static str,
+    entries: Vec<Entry>,
+    selected: usize,
+
+    backdrop: [f32; 4],
+}
+
+
+impl Menu {
+    fn new(title: &'static str, entries: Vec<Entry>, backdrop: [f32; 4]) -> Menu {
+        Menu {
+            title,
+            entries,
+            selected: 0,
+
+            backdrop,
+        }
+    }
+
+
+    fn selected(&self) -> Entry {
+        self.entries[self.selected]
+    }
+
+
+    /// Move the selection, returning the actions that were pressed
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Vec<Action> {
+        // The game ignores presses while paused, but needs to see released inputs
+        game.handle_input(event);
+
+        let actions = match event {
+            InputEvent::Pressed(binding) => game.bound_actions(binding),
+            _ => return Vec::new()
+        };
+
+        let count = self.entries.len();
+
+        for &action in actions.iter() {
+            match action {
+                Action::MenuUp => self.selected = (self.selected + count - 1) % count,
+                Action::MenuDown => self.selected = (self.selected + 1) % count,
+
+                Action::MenuLeft => self.selected().adjust(game, -1.0),
+                Action::MenuRight => self.selected().adjust(game, 1.0),
+
+                _ => ()
+            }
+        }
+
+        actions
+    }
+
+
+    /// Draw the frozen game with the menu on top
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        game.draw(frame);
+        game.begin_ui(frame);
+
+        let (width, height) = game.screen_size();
+
+        frame.draw(&Overlay {
+            width,
+            height,
+            color: self.backdrop,
+        });
+
+        frame.clear_depth();
+
+        let entries: Vec<(&str, Option<f64>)> = self.entries.iter()
+            .map(|entry| { (entry.label(), entry.value(game)) })
+            .collect();
+
+        frame.draw(&MenuPanel {
+            x: width / 2.0,
+            y: height / 2.0,
+
+            title: self.title,
+            entries: &entries,
+            selected: self.selected,
+        });
+    }
+}
+
+
+
+struct MainMenu {
+    menu: Menu,
+}
+
+
+impl MainMenu {
+    fn new() -> MainMenu {
+        MainMenu {
+            menu: Menu::new("Rogue", vec![Entry::Play, Entry::Options, Entry::Quit], [0.0, 0.0, 0.0, 0.8]),
+        }
+    }
+}
+
+
+impl State for MainMenu {
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        self.menu.draw(game, frame);
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        for action in self.menu.handle_input(game, event) {
+            if action == Action::MenuSelect {
+                return match self.menu.selected() {
+                    Entry::Play => Transition::Replace(Box::new(Playing)),
+                    Entry::Options => Transition::Push(Box::new(Options::new())),
+                    Entry::Quit => Transition::Quit,
+
+                    _ => Transition::None
+                };
+            }
+        }
+
+        Transition::None
+    }
+}
+
+
+
+struct Paused {
+    menu: Menu,
+}
+
+
+impl Paused {
+    fn new() -> Paused {
+        let entries = vec![Entry::Resume, Entry::Options, Entry::MainMenu, Entry::Quit];
+
+        Paused {
+            menu: Menu::new("Paused", entries, [0.0, 0.0, 0.0, 0.5]),
+        }
+    }
+}
+
+
+impl State for Paused {
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        self.menu.draw(game, frame);
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        for action in self.menu.handle_input(game, event) {
+            match action {
+                Action::MenuBack => return Transition::Pop,
+
+                Action::MenuSelect => {
+                    return match self.menu.selected() {
+                        Entry::Resume => Transition::Pop,
+                        Entry::Options => Transition::Push(Box::new(Options::new())),
+                        Entry::MainMenu => Transition::MainMenu,
+                        Entry::Quit => Transition::Quit,
+
+                        _ => Transition::None
+                    };
+                }
+
+                _ => ()
+            }
+        }
+
+        Transition::None
+    }
+}
+
+
+
+struct Options {
+    menu: Menu,
+}
+
+
+impl Options {
+    fn new() -> Options {
+        let entries = vec![
+            Entry::Sensitivity, Entry::InvertY, Entry::Smoothing, Entry::FieldOfView,
+            Entry::DrawDistance, Entry::Fog, Entry::Back,
+        ];
+
+        Options {
+            menu: Menu::new("Options", entries, [0.0, 0.0, 0.0, 0.8]),
+        }
+    }
+}
+
+
+impl State for Options {
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        self.menu.draw(game, frame);
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        for action in self.menu.handle_input(game, event) {
+            match action {
+                Action::MenuBack => return Transition::Pop,
+
+                Action::MenuSelect => {
+                    match self.menu.selected() {
+                        Entry::Back => return Transition::Pop,
+                        entry => entry.adjust(game, 1.0),
+                    }
+                }
+
+                _ => ()
+            }
+        }
+
+        Transition::None
+    }
+}
+
+
+
+/// Commands typed over the frozen game
+struct ConsoleScreen {
+    input: String,
+
+    /// The entry in the history being edited, if any
+    history_index: Option<usize>,
+}
+
+
+impl ConsoleScreen {
+    fn new() -> ConsoleScreen {
+        ConsoleScreen {
+            input: String::new(),
+            history_index: None,
+        }
+    }
+
+
+    fn browse_history(&mut self, game: &GameState, older: bool) {
+        let history = game.console().history();
+
+        if history.is_empty() {
+            return;
+        }
+
+        self.history_index = match (self.history_index, older) {
+            (None, true) => Some(history.len() - 1),
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < history.len() => Some(index + 1),
+
+            _ => None
+        };
+
+        self.input = match self.history_index {
+            Some(index) => history[index].clone(),
+            None => String::new(),
+        };
+    }
+}
+
+
+impl State for ConsoleScreen {
+    fn draw(&self, game: &mut GameState, frame: &mut Frame) {
+        game.draw(frame);
+        game.begin_ui(frame);
+
+        let (width, height) = game.screen_size();
+        let bottom = (height / 2.0).floor();
+
+        frame.draw(&Panel {
+            min: (-2.0, -2.0),
+            max: (width + 2.0, bottom),
+            color: [0.05, 0.05, 0.08, 0.85],
+            border: [0.4, 0.4, 0.5, 1.0],
+        });
+
+        frame.clear_depth();
+
+        let margin = 12.0;
+        let line_height = GLYPH_HEIGHT as f64 * CONSOLE_TEXT_SCALE + CONSOLE_LINE_SPACING;
+
+        let color = [0.8, 0.8, 0.8, 1.0];
+
+        // The input line sits at the bottom with the most recent output above it
+        let prompt = format!("> {}", self.input);
+        let mut y = bottom - margin - line_height;
+
+        frame.draw(&Text {
+            x: margin,
+            y,
+
+            text: &prompt,
+            scale: CONSOLE_TEXT_SCALE,
+            color: [0.9, 0.8, 0.2, 1.0],
+        });
+
+        let cursor = margin + text_width(&prompt, CONSOLE_TEXT_SCALE) + CONSOLE_TEXT_SCALE;
+
+        frame.draw(&Panel {
+            min: (cursor, y),
+            max: (cursor + GLYPH_WIDTH as f64 * CONSOLE_TEXT_SCALE, y + GLYPH_HEIGHT as f64 * CONSOLE_TEXT_SCALE),
+            color: [0.9, 0.8, 0.2, 1.0],
+            border: [0.9, 0.8, 0.2, 1.0],
+        });
+
+        for line in game.console().log().iter().rev() {
+            y -= line_height;
+
+            if y < margin {
+                break;
+            }
+
+            frame.draw(&Text {
+                x: margin,
+                y,
+
+                text: line,
+                scale: CONSOLE_TEXT_SCALE,
+                color,
+            });
+        }
+    }
+
+    fn handle_input(&mut self, game: &mut GameState, event: InputEvent) -> Transition {
+        game.handle_input(event);
+
+        let binding = match event {
+            InputEvent::Pressed(binding) => binding,
+            _ => return Transition::None
+        };
+
+        match binding {
+            Binding::Key(VirtualKeyCode::Return) => {
+                if !self.input.trim().is_empty() {
+                    let _ = game.run_command(&self.input);
+                }
+
+                self.input.clear();
+                self.history_index = None;
+            }
+
+            Binding::Key(VirtualKeyCode::Back) => {
+                self.input.pop();
+            }
+
+            Binding::Key(VirtualKeyCode::Up) => self.browse_history(game, true),
+            Binding::Key(VirtualKeyCode::Down) => self.browse_history(game, false),
+
+            _ => {
+                let actions = game.bound_actions(binding);
+
+                if actions.contains(&Action::Console) || actions.contains(&Action::MenuBack) {
+                    return Transition::Pop;
+                }
+            }
+        }
+
+        Transition::None
+    }
+
+    fn handle_char(&mut self, _game: &mut GameState, c: char) -> Transition {
+        // The key that opens the console types one of these
+        if !c.is_control() && c != '`' && c != '~' {
+            self.input.push(c);
+        }
+
+        Transition::None
+    }
+}
@@ -0,0 +1,306 @@
+use graphics_3d::{
+    PI,
+
+    trap::{
+        Vector3
+    },
+    Color
+};
+
+use rand::Rng;
+
+use bounding_box::BoundingBox;
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FireMode {
+    /// Instantly hits whatever is in front of the barrel
+    HitScan,
+
+    /// Fires a number of hit-scan pellets at once
+    Spread { pellets: u32 },
+
+    /// Launches a slow projectile that explodes on impact
+    Projectile { speed: f64, radius: f64 },
+}
+
+
+/// The fixed properties of a type of weapon
+pub struct WeaponDef {
+    pub name: &'static str,
+    pub mode: FireMode,
+
+    /// Damage per shot, or per pellet
+    pub damage: f64,
+
+    pub magazine: u32,
+    pub max_reserve: u32,
+
+    /// Shots per second
+    pub fire_rate: f64,
+    /// Seconds it takes to reload
+    pub reload_time: f64,
+    /// Maximum angle in radians a shot may deviate from the aim
+    pub spread: f64,
+
+    /// Keep firing while the trigger is held
+    pub automatic: bool,
+
+    /// Trauma added to the camera shake by each shot
+    pub shake: f64,
+}
+
+
+pub static WEAPONS: [WeaponDef; 3] = [
+    WeaponDef {
+        name: "Rifle",
+        mode: FireMode::HitScan,
+        damage: 20.0,
+        magazine: 30,
+        max_reserve: 120,
+        fire_rate: 10.0,
+        reload_time: 1.5,
+        spread: 0.01,
+        automatic: true,
+        shake: 0.12,
+    },
+    WeaponDef {
+        name: "Launcher",
+        mode: FireMode::Projectile { speed: 25.0, radius: 3.0 },
+        damage: 80.0,
+        magazine: 4,
+        max_reserve: 12,
+        fire_rate: 1.25,
+        reload_time: 2.5,
+        spread: 0.0,
+        automatic: false,
+        shake: 0.35,
+    },
+    WeaponDef {
+        name: "Shotgun",
+        mode: FireMode::Spread { pellets: 8 },
+        damage: 12.0,
+        magazine: 6,
+        max_reserve: 36,
+        fire_rate: 1.5,
+        reload_time: 2.0,
+        spread: 0.08,
+        automatic: false,
+        shake: 0.4,
+    },
+];
+
+
+
+pub struct Weapon {
+    pub def: &'static WeaponDef,
+    pub owned: bool,
+
+    pub ammo: u32,
+    pub reserve: u32,
+
+    cooldown: f64,
+    reload: Option<f64>,
+}
+
+
+impl Weapon {
+    fn new(def: &'static WeaponDef) -> Weapon {
+        Weapon {
+            def,
+            owned: false,
+
+            ammo: def.magazine,
+            reserve: def.max_reserve / 2,
+
+            cooldown: 0.0,
+            reload: None,
+        }
+    }
+
+
+    pub fn reloading(&self) -> bool {
+        self.reload.is_some()
+    }
+
+    /// Add a number of magazines to the reserve, returns false if the reserve is already full
+    fn add_ammo(&mut self, magazines: u32) -> bool {
+        if self.reserve >= self.def.max_reserve {
+            return false;
+        }
+
+        self.reserve = (self.reserve + magazines * self.def.magazine).min(self.def.max_reserve);
+        true
+    }
+
+    fn start_reload(&mut self) {
+        if self.reload.is_none() && self.ammo < self.def.magazine && self.reserve > 0 {
+            self.reload = Some(self.def.reload_time);
+        }
+    }
+
+
+    fn update(&mut self, dt: f64) {
+        if self.cooldown > 0.0 {
+            self.cooldown -= dt;
+        }
+
+        if let Some(remaining) = self.reload {
+            if remaining > dt {
+                self.reload = Some(remaining - dt);
+            } else {
+                let loaded = (self.def.magazine - self.ammo).min(self.reserve);
+                self.ammo += loaded;
+                self.reserve -= loaded;
+                self.reload = None;
+            }
+        }
+    }
+}
+
+
+
+/// The weapons carried by the player
+pub struct Arsenal {
+    weapons: Vec<Weapon>,
+    current: usize,
+
+    trigger: bool,
+    fired: bool,
+}
+
+
+impl Arsenal {
+    pub fn new() -> Arsenal {
+        let mut weapons: Vec<Weapon> = WEAPONS.iter().map(Weapon::new).collect();
+        weapons[0].owned = true;
+
+        Arsenal {
+            weapons,
+            current: 0,
+
+            trigger: false,
+            fired: false,
+        }
+    }
+
+
+    pub fn current(&self) -> &Weapon {
+        &self.weapons[self.current]
+    }
+
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.weapons.len() && index != self.current && self.weapons[index].owned {
+            // Switching weapons cancels the reload
+            self.weapons[self.current].reload = None;
+            self.current = index;
+            self.fired = false;
+        }
+    }
+
+    pub fn set_trigger(&mut self, held: bool) {
+        self.trigger = held;
+
+        if !held {
+            self.fired = false;
+        }
+    }
+
+    /// Give the player a weapon, or a magazine of ammo for it if it is already owned
+    pub fn give(&mut self, index: usize) -> bool {
+        if index >= self.weapons.len() {
+            return false;
+        }
+
+        if self.weapons[index].owned {
+            self.weapons[index].add_ammo(1)
+        } else {
+            self.weapons[index].owned = true;
+            self.select(index);
+            true
+        }
+    }
+
+    /// Add ammo for the current weapon
+    pub fn add_ammo(&mut self) -> bool {
+        self.weapons[self.current].add_ammo(2)
+    }
+
+    pub fn reload(&mut self) {
+        self.weapons[self.current].start_reload();
+    }
+
+
+    /// Advance the weapon timers, returning the weapon that fired during this step
+    pub fn update(&mut self, dt: f64) -> Option<&'static WeaponDef> {
+        for weapon in self.weapons.iter_mut() {
+            weapon.update(dt);
+        }
+
+        let weapon = &mut self.weapons[self.current];
+
+        if !self.trigger || weapon.reloading() || weapon.cooldown > 0.0 {
+            return None;
+        }
+
+        if self.fired && !weapon.def.automatic {
+            return None;
+        }
+
+        if weapon.ammo == 0 {
+            weapon.start_reload();
+            return None;
+        }
+
+        weapon.ammo -= 1;
+        weapon.cooldown = 1.0 / weapon.def.fire_rate;
+        self.fired = true;
+
+        Some(weapon.def)
+    }
+}
+
+
+/// Randomly deviate a direction by at most `spread` radians
+pub fn scatter<R: Rng>(direction: Vector3, spread: f64, rng: &mut R) -> Vector3 {
+    if spread <= 0.0 {
+        return direction;
+    }
+
+    let perp_x = direction.cross(Vector3::new(
+        rng.gen_range(0.01, 1.0),
+        rng.gen_range(0.01, 1.0),
+        rng.gen_range(0.01, 1.0)
+    )).normal();
+
+    let perp_y = perp_x.cross(direction);
+
+    let theta = rng.gen_range(0.0, 2.0 * PI);
+    let r = rng.gen_range(0.0, spread.tan());
+    let (dy, dx) = theta.sin_cos();
+
+    (direction + r * dx * perp_x + r * dy * perp_y).normal()
+}
+
+
+
+pub struct Projectile {
+    pub position: Vector3,
+    pub velocity: Vector3,
+
+    pub damage: f64,
+    pub radius: f64,
+
+    pub lifetime: f64,
+}
+
+
+impl Projectile {
+    pub fn hull(&self) -> BoundingBox {
+        let mut b = BoundingBox::cube(self.position, 0.08);
+        b.color = Some(Color::new(1.0, 0.5, 0.1, 1.0));
+        b
+    }
+}
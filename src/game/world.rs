@@ -1,3 +1,10 @@
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    ptr,
+};
+
 use graphics_3d::{
     trap::{
         Vector3,
@@ -5,19 +12,30 @@ use graphics_3d::{
     },
     Draw,
     DrawCommand,
-    Color
+    Vertex,
+    Color,
 };
 
 use rand::{
-    thread_rng,
-    Rng
+    Rng,
+    rngs::StdRng,
 };
 
 use bounding_box::{
     BoundingBox,
     Rectangle,
+    Frustum,
+};
+
+use mesh::Mesh;
+
+use lighting::{
+    Lighting,
+    PointLight,
 };
 
+use stopwatch::Stopwatch;
+
 
 
 
@@ -25,25 +43,110 @@ const PASSAGE_WIDTH: f64 = 3.0;
 const WALL_THICKNESS: f64 = 0.5;
 const WALL_HEIGHT: f64 = 5.5;
 
+/// Damage a spot on a wall can take before a hole is carved
+const WALL_STRENGTH: f64 = 100.0;
+
+/// Hits closer than this to an earlier impact add to its damage
+const IMPACT_RADIUS: f64 = 0.75;
+
+/// Width of the holes carved in walls
+const HOLE_SIZE: f64 = 1.5;
+
+/// Brightness of rooms that have been seen but not entered
+const SEEN_BRIGHTNESS: f32 = 0.35;
+
+/// Distance between torches along the walls of a room
+const TORCH_SPACING: f64 = 8.0;
+const TORCH_HEIGHT: f64 = 3.0;
+
+/// How far torches stand out from the wall they hang on
+const TORCH_OFFSET: f64 = 0.2;
+
+const TORCH_RADIUS: f64 = 9.0;
+const TORCH_COLOR: [f32; 3] = [1.0, 0.62, 0.3];
+
+/// Half the width of the flame drawn at each torch
+const FLAME_SIZE: f64 = 0.08;
+
 
 
 /// A world made up of rooms
 pub struct World {
     rooms: Vec<Room>,
-    passages: Vec<Room>
+    passages: Vec<Room>,
+
+    /// The indices of the two rooms each passage connects
+    links: Vec<(usize, usize)>,
+
+    /// Generates the layout, the same seed always gives the same rooms when explored in the same order
+    rng: StdRng,
+    seed: u64,
+
+    /// The rooms in the order they were explored, enough to generate the same layout again
+    explored: Vec<usize>,
 }
 
 
 impl World {
-    pub fn new() -> World {
-        World {
-            rooms: vec![
-                Room::new(Rectangle {
-                    min: Vector2::new(-8.0, -8.0),
-                    max: Vector2::new(8.0, 8.0),
-                })
-            ],
-            passages: Vec::new()
+    pub fn new(seed: u64) -> World {
+        let mut start = Room::new(Rectangle {
+            min: Vector2::new(-8.0, -8.0),
+            max: Vector2::new(8.0, 8.0),
+        });
+        start.place_torches();
+
+        let mut world = World {
+            rooms: vec![start],
+            passages: Vec::new(),
+            links: Vec::new(),
+
+            rng: super::seeded_rng(seed),
+            seed,
+
+            explored: Vec::new(),
+        };
+
+        world.rebuild_meshes();
+        world
+    }
+
+
+    /// Generate a saved world again, with the rooms and passages as the player knew them.
+    /// Holes carved in the walls aren't saved.
+    pub fn restore(snapshot: &WorldSnapshot) -> Result<World, String> {
+        let mut world = World::new(snapshot.seed);
+
+        for &room in snapshot.explored.iter() {
+            if room >= world.rooms.len() {
+                return Err(format!("room {} is explored before it is generated", room));
+            }
+
+            world.generate_around(room);
+        }
+
+        if world.rooms.len() != snapshot.rooms.len() || world.passages.len() != snapshot.passages.len() {
+            return Err("the saved rooms don't match the ones generated from the seed".to_string());
+        }
+
+        let rooms = world.rooms.iter_mut().zip(snapshot.rooms.iter());
+        let passages = world.passages.iter_mut().zip(snapshot.passages.iter());
+
+        for (room, &visibility) in rooms.chain(passages) {
+            room.visibility = visibility;
+            room.dirty = true;
+        }
+
+        world.rebuild_meshes();
+        Ok(world)
+    }
+
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot {
+            seed: self.seed,
+            explored: self.explored.clone(),
+
+            rooms: self.rooms.iter().map(|room| { room.visibility }).collect(),
+            passages: self.passages.iter().map(|passage| { passage.visibility }).collect(),
         }
     }
 
@@ -54,128 +157,428 @@ impl World {
     }
 
 
-    pub fn explore(&mut self, position: Vector3) {
-        if let Some(room_index) = self.find_room_index(position) {
-            if self.rooms[room_index].explored {
-                return;
-            } else {
-                self.rooms[room_index].explored = true;
-                if let Some(ref mut b) = self.rooms[room_index].boxes.first_mut() {
-                    b.color = Some(Color::new(0.0, 1.0, 0.0, 1.0));
+    /// The seed the layout is generated from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Number of rooms generated so far
+    pub fn room_count(&self) -> usize {
+        self.rooms.len()
+    }
+
+    pub fn explored_count(&self) -> usize {
+        self.rooms.iter().filter(|room| { room.visibility == Visibility::Explored }).count()
+    }
+
+
+    /// The floor of every room and passage, with how much of it the player knows about
+    pub fn floors<'a>(&'a self) -> impl Iterator<Item=(Rectangle, Visibility)> + 'a {
+        self.rooms.iter()
+            .chain(self.passages.iter())
+            .map(|room| { (room.floor, room.visibility) })
+    }
+
+    /// How much the player knows about the room or passage at a position.
+    /// Positions outside the world are always visible.
+    pub fn visibility(&self, position: Vector3) -> Visibility {
+        let point = Vector2::new(position.x, position.z);
+
+        self.rooms.iter()
+            .chain(self.passages.iter())
+            .find(|room| { room.floor.contains(point) })
+            .map_or(Visibility::Explored, |room| { room.visibility })
+    }
+
+
+    /// Every room and passage the player has seen
+    pub fn visible_rooms(&self) -> impl Iterator<Item=&Room> {
+        self.rooms.iter()
+            .chain(self.passages.iter())
+            .filter(|room| { room.visibility != Visibility::Hidden })
+    }
+
+    /// The seen rooms and passages in view, found by walking from the room the eye is in through the openings
+    /// of passages in view, and out through the holes blown in the walls.
+    pub fn portal_rooms<'a>(&'a self, frustum: &Frustum) -> Vec<&'a Room> {
+        let in_view = || {
+            self.visible_rooms()
+                .filter(|room| { frustum.intersects(&room.bounds()) })
+                .collect()
+        };
+
+        let point = Vector2::new(frustum.eye.x, frustum.eye.z);
+        let mut seen = Vec::new();
+
+        if let Some(passage) = self.passages.iter().position(|passage| { passage.floor.contains(point) }) {
+            let (a, b) = self.links[passage];
+
+            seen.push(&self.passages[passage]);
+            self.look_through_holes(&self.passages[passage], frustum, &mut seen);
+            self.look_into(a, passage, frustum, &mut seen);
+            self.look_into(b, passage, frustum, &mut seen);
+        } else if let Some(room) = self.find_room_index(frustum.eye) {
+            self.look_around(room, None, frustum, &mut seen);
+        } else {
+            return in_view();
+        }
+
+        seen.retain(|room| { room.visibility != Visibility::Hidden });
+
+        // Rooms seen through a hole may be seen through the passages as well
+        let mut unique: Vec<&Room> = Vec::with_capacity(seen.len());
+        for room in seen {
+            if !unique.iter().any(|&other| { ptr::eq(other, room) }) {
+                unique.push(room);
+            }
+        }
+
+        unique
+    }
+
+
+    /// Seconds it takes to build the commands for every seen room, averaged over a number of frames.
+    /// Returns the time taken the way rooms were drawn before they kept meshes, then the time taken now.
+    pub fn benchmark(&self, frames: usize, lighting: &Lighting) -> (f64, f64) {
+        let frames = frames.max(1);
+        let mut stopwatch = Stopwatch::new();
+
+        for _ in 0..frames {
+            let commands: Vec<DrawCommand> = self.visible_rooms().map(|room| { room.draw_box_by_box() }).collect();
+            drop(commands);
+        }
+        let before = stopwatch.tick();
+
+        for _ in 0..frames {
+            let commands: Vec<DrawCommand> = self.visible_rooms().map(|room| { room.lit(lighting) }).collect();
+            drop(commands);
+        }
+        let after = stopwatch.tick();
+
+        (before / frames as f64, after / frames as f64)
+    }
+
+    /// Triangles in every seen room, the way rooms were drawn before they kept meshes and now
+    pub fn triangle_counts(&self) -> (usize, usize) {
+        self.visible_rooms().fold((0, 0), |(before, after), room| {
+            (before + 12 * room.boxes.len(), after + room.mesh.triangle_count() + room.flames.triangle_count())
+        })
+    }
+
+
+    /// The torches of the room or passage at a position
+    pub fn lights_at(&self, position: Vector3) -> &[PointLight] {
+        let point = Vector2::new(position.x, position.z);
+
+        self.rooms.iter()
+            .chain(self.passages.iter())
+            .find(|room| { room.floor.contains(point) })
+            .map_or(&[], |room| { room.torches() })
+    }
+
+
+    /// The floor of the room the player starts in
+    pub fn start_floor(&self) -> Rectangle {
+        self.rooms[0].floor
+    }
+
+
+    /// Explore the room at a position, returning the floors of any newly generated rooms
+    pub fn explore(&mut self, position: Vector3) -> Vec<Rectangle> {
+        let generated = match self.find_room_index(position) {
+            Some(room_index) => self.generate_around(room_index),
+            None => Vec::new(),
+        };
+
+        self.rebuild_meshes();
+        generated
+    }
+
+    /// Mark a room as explored, generating the rooms around it the first time
+    fn generate_around(&mut self, room_index: usize) -> Vec<Rectangle> {
+        let mut generated = Vec::new();
+
+        if self.rooms[room_index].visibility == Visibility::Explored {
+            return generated;
+        } else {
+            self.rooms[room_index].visibility = Visibility::Explored;
+            self.rooms[room_index].dirty = true;
+            self.explored.push(room_index);
+        }
+
+        let mut new_rooms = Vec::new();
+        let rng = &mut self.rng;
+
+
+        // Attempt to add 4 new rooms
+        for _ in 0..4 {
+            let (dx, dy) = {
+                let direction = rng.gen_range(0, 4);
+
+                match direction {
+                    0 => (1, 0),
+                    1 => (0, 1),
+                    2 => (-1, 0),
+                    3 => (0, -1),
+
+                    _ => panic!()
                 }
+            };
+
+
+            let floor = self.rooms[room_index].floor;
+            let distance = rng.gen_range(
+                2.0 * WALL_THICKNESS + 2.0,
+                2.0 * WALL_THICKNESS + 6.0
+            );
+
+            let new_size = Vector2::new(
+                rng.gen_range(8.0, 24.0),
+                rng.gen_range(8.0, 24.0)
+            );
+            let new_center = floor.center() +
+                Vector2::new(dx as f64, dy as f64) *
+                    (0.5 * (floor.size() + new_size) + Vector2::new(distance, distance));
+
+
+            let new_floor = if dy == 0 {
+                let min_y = floor.min.y + PASSAGE_WIDTH + 1.0;
+                let max_y = floor.max.y - PASSAGE_WIDTH - 1.0;
+
+                let min = rng.gen_range(min_y - new_size.y, max_y);
+                let max = min + new_size.y;
+
+                Rectangle::centered(
+                    Vector2::new(new_center.x, 0.5 * (min + max)),
+                    new_size
+                )
+            } else {
+                let min_x = floor.min.x + PASSAGE_WIDTH + 1.0;
+                let max_x = floor.max.x - PASSAGE_WIDTH - 1.0;
+
+                let min = rng.gen_range(min_x - new_size.x, max_x);
+                let max = min + new_size.x;
+
+                Rectangle::centered(
+                    Vector2::new(0.5 * (min + max), new_center.y),
+                    new_size
+                )
+            };
+
+
+            let margin = 2.0 * WALL_THICKNESS + 0.8;
+            let tmp_floor = Rectangle::centered(
+                new_floor.center(),
+                new_size + 2.0 * Vector2::new(margin, margin)
+            );
+
+            if !self.rooms.iter()
+                .any(|room|{tmp_floor.intersects(&room.floor)}) {
+                let mut room = Room::new(new_floor);
+                room.place_torches();
+
+                new_rooms.push(self.rooms.len());
+                self.rooms.push(room);
             }
+        }
+
 
-            let mut new_rooms = Vec::new();
-            let mut rng = thread_rng();
+        // Add passages between rooms
+        let old = self.rooms[room_index].floor;
+        for i in new_rooms.into_iter() {
+            let new = self.rooms[i].floor;
+
+            // Calculate two opposite corners of the floor
+            let a = Vector2::new(
+                if old.min.x > new.min.x {old.min.x} else {new.min.x},
+                if old.min.y > new.min.y {old.min.y} else {new.min.y}
+            );
+            let b = Vector2::new(
+                if old.max.x < new.max.x {old.max.x} else {new.max.x},
+                if old.max.y < new.max.y {old.max.y} else {new.max.y}
+            );
+
+            // Construct the floor
+            let mut floor = Rectangle::new(a, b);
+
+            if old.min.x < new.max.x && new.min.x < old.max.x {
+                // Intersect on x
+                floor.min.x += rng.gen_range(0.0, floor.size().x - PASSAGE_WIDTH);
+                floor.max.x = floor.min.x + PASSAGE_WIDTH;
+            } else {
+                // Intersect on y
+                floor.min.y += rng.gen_range(0.0, floor.size().y - PASSAGE_WIDTH);
+                floor.max.y = floor.min.y + PASSAGE_WIDTH;
+            }
 
+            let mut passage = Room::new(floor);
 
-            // Attempt to add 4 new rooms
-            for _ in 0..4 {
-                let (dx, dy) = {
-                    let direction = rng.gen_range(0, 4);
+            passage.cut_walls(self.rooms[room_index].area());
+            passage.cut_walls(self.rooms[i].area());
 
-                    match direction {
-                        0 => (1, 0),
-                        1 => (0, 1),
-                        2 => (-1, 0),
-                        3 => (0, -1),
+            self.rooms[room_index].cut_walls(floor);
+            self.rooms[i].cut_walls(floor);
 
-                        _ => panic!()
+            self.passages.push(passage);
+            self.links.push((room_index, i));
+
+            generated.push(new);
+        }
+
+        generated
+    }
+
+
+    /// Reveal what can be seen from a position.
+    /// The passages leaving the room the eye is in are seen, as are the rooms at the other end if the
+    /// middle of the passage is in sight. Entering a passage reveals both rooms it connects.
+    pub fn reveal(&mut self, eye: Vector3) {
+        let point = Vector2::new(eye.x, eye.z);
+
+        if let Some(passage) = self.passages.iter().position(|passage| { passage.floor.contains(point) }) {
+            let (a, b) = self.links[passage];
+
+            self.passages[passage].visibility = Visibility::Explored;
+            self.passages[passage].dirty = true;
+            self.rooms[a].see();
+            self.rooms[b].see();
+
+            self.rebuild_meshes();
+            return;
+        }
+
+        let room_index = match self.find_room_index(eye) {
+            Some(index) => index,
+            None => return,
+        };
+
+        for passage in 0..self.passages.len() {
+            let other = match self.links[passage] {
+                (a, b) if a == room_index => b,
+                (a, b) if b == room_index => a,
+                _ => continue,
+            };
+
+            self.passages[passage].see();
+
+            if self.rooms[other].visibility != Visibility::Hidden {
+                continue;
+            }
+
+            let center = self.passages[passage].floor.center();
+            let target = Vector3::new(center.x, eye.y.clamp(0.5, WALL_HEIGHT - 0.5), center.y);
+
+            // Hit distances are measured in lengths of the direction, anything past 1 is behind the target
+            let blocked = self.hit_scan(eye, target - eye)
+                .is_some_and(|hit| { hit.distance < 1.0 });
+
+            if !blocked {
+                self.rooms[other].see();
+            }
+        }
+
+        self.rebuild_meshes();
+    }
+
+
+    /// Find the closest box hit by a ray
+    pub fn hit_scan(&self, origin: Vector3, direction: Vector3) -> Option<WallHit> {
+        let rooms = self.rooms.iter().enumerate()
+            .map(|(i, room)| { (RoomRef::Room(i), room) });
+        let passages = self.passages.iter().enumerate()
+            .map(|(i, passage)| { (RoomRef::Passage(i), passage) });
+
+        let mut closest: Option<WallHit> = None;
+
+        for (room, r) in rooms.chain(passages) {
+            for (wall, b) in r.boxes.iter().enumerate() {
+                if let Some((distance, normal)) = b.hit_scan(origin, direction) {
+                    if closest.as_ref().is_none_or(|hit| { distance < hit.distance }) {
+                        closest = Some(WallHit {
+                            distance,
+                            normal,
+                            point: origin + distance * direction,
+
+                            room,
+                            wall,
+                        });
                     }
-                };
-
-
-                let floor = self.rooms[room_index].floor;
-                let distance = rng.gen_range(
-                    2.0 * WALL_THICKNESS + 2.0,
-                    2.0 * WALL_THICKNESS + 6.0
-                );
-
-                let new_size = Vector2::new(
-                    rng.gen_range(8.0, 24.0),
-                    rng.gen_range(8.0, 24.0)
-                );
-                let new_center = floor.center() +
-                    Vector2::new(dx as f64, dy as f64) *
-                        (0.5 * (floor.size() + new_size) + Vector2::new(distance, distance));
-
-
-                let new_floor = if dy == 0 {
-                    let min_y = floor.min.y + PASSAGE_WIDTH + 1.0;
-                    let max_y = floor.max.y - PASSAGE_WIDTH - 1.0;
-
-                    let min = rng.gen_range(min_y - new_size.y, max_y);
-                    let max = min + new_size.y;
-
-                    Rectangle::centered(
-                        Vector2::new(new_center.x, 0.5 * (min + max)),
-                        new_size
-                    )
-                } else {
-                    let min_x = floor.min.x + PASSAGE_WIDTH + 1.0;
-                    let max_x = floor.max.x - PASSAGE_WIDTH - 1.0;
-
-                    let min = rng.gen_range(min_x - new_size.x, max_x);
-                    let max = min + new_size.x;
-
-                    Rectangle::centered(
-                        Vector2::new(0.5 * (min + max), new_center.y),
-                        new_size
-                    )
-                };
-
-
-                let margin = 2.0 * WALL_THICKNESS + 0.8;
-                let tmp_floor = Rectangle::centered(
-                    new_floor.center(),
-                    new_size + 2.0 * Vector2::new(margin, margin)
-                );
-
-                if !self.rooms.iter()
-                    .any(|room|{tmp_floor.intersects(&room.floor)}) {
-                    new_rooms.push(self.rooms.len());
-                    self.rooms.push(Room::new(new_floor));
                 }
             }
+        }
 
+        closest
+    }
 
-            // Add passages between rooms
-            let old = self.rooms[room_index].floor;
-            for i in new_rooms.into_iter() {
-                let new = self.rooms[i].floor;
-
-                // Calculate two opposite corners of the floor
-                let a = Vector2::new(
-                    if old.min.x > new.min.x {old.min.x} else {new.min.x},
-                    if old.min.y > new.min.y {old.min.y} else {new.min.y}
-                );
-                let b = Vector2::new(
-                    if old.max.x < new.max.x {old.max.x} else {new.max.x},
-                    if old.max.y < new.max.y {old.max.y} else {new.max.y}
-                );
-
-                // Construct the floor
-                let mut floor = Rectangle::new(a, b);
-
-                if old.min.x < new.max.x && new.min.x < old.max.x {
-                    // Intersect on x
-                    floor.min.x += rng.gen_range(0.0, floor.size().x - PASSAGE_WIDTH);
-                    floor.max.x = floor.min.x + PASSAGE_WIDTH;
-                } else {
-                    // Intersect on y
-                    floor.min.y += rng.gen_range(0.0, floor.size().y - PASSAGE_WIDTH);
-                    floor.max.y = floor.min.y + PASSAGE_WIDTH;
-                }
 
-                let mut passage = Room::new(floor);
+    /// Damage the wall that was hit, carving a hole if it has taken enough damage.
+    /// Returns true if a hole was carved.
+    pub fn damage(&mut self, hit: &WallHit, amount: f64) -> bool {
+        let room = match hit.room {
+            RoomRef::Room(i) => &mut self.rooms[i],
+            RoomRef::Passage(i) => &mut self.passages[i],
+        };
+
+        let carved = room.damage(hit.wall, hit.point, hit.normal, amount);
+
+        if carved {
+            room.rebuild_mesh();
+        }
+
+        carved
+    }
+
+
+    /// Rebuild the meshes of the rooms and passages that changed since they were last built
+    fn rebuild_meshes(&mut self) {
+        for room in self.rooms.iter_mut().chain(self.passages.iter_mut()).filter(|room| { room.dirty }) {
+            room.rebuild_mesh();
+        }
+    }
+
+
+    /// Look through the opening from a passage into a room, and on through the room's other passages
+    fn look_into<'a>(&'a self, room: usize, passage: usize, frustum: &Frustum, seen: &mut Vec<&'a Room>) {
+        let opening = opening(&self.rooms[room], &self.passages[passage]);
+
+        if let Some(frustum) = frustum.through(&opening) {
+            self.look_around(room, Some(passage), &frustum, seen);
+        }
+    }
+
+    /// See a room and look through the passages leaving it, except the one the view came through.
+    /// Passages only ever lead to newly generated rooms, so the walk can't go in circles.
+    fn look_around<'a>(&'a self, room: usize, from: Option<usize>, frustum: &Frustum, seen: &mut Vec<&'a Room>) {
+        seen.push(&self.rooms[room]);
+        self.look_through_holes(&self.rooms[room], frustum, seen);
+
+        for passage in 0..self.passages.len() {
+            if from == Some(passage) {
+                continue;
+            }
 
-                passage.cut_walls(self.rooms[room_index].area());
-                passage.cut_walls(self.rooms[i].area());
+            let other = match self.links[passage] {
+                (a, b) if a == room => b,
+                (a, b) if b == room => a,
+                _ => continue,
+            };
 
-                self.rooms[room_index].cut_walls(floor);
-                self.rooms[i].cut_walls(floor);
+            let opening = opening(&self.rooms[room], &self.passages[passage]);
 
-                self.passages.push(passage);
+            if let Some(frustum) = frustum.through(&opening) {
+                seen.push(&self.passages[passage]);
+                self.look_through_holes(&self.passages[passage], &frustum, seen);
+                self.look_into(other, passage, &frustum, seen);
+            }
+        }
+    }
+
+    /// Look out through the holes blown in the walls of a room or passage.
+    /// Anything could be behind a hole, so every seen room in view through it is seen.
+    fn look_through_holes<'a>(&'a self, room: &Room, frustum: &Frustum, seen: &mut Vec<&'a Room>) {
+        for &hole in room.holes.iter() {
+            if let Some(frustum) = frustum.through(&hole_opening(room.floor, hole)) {
+                seen.extend(self.visible_rooms().filter(|other| { frustum.intersects(&other.bounds()) }));
             }
         }
     }
@@ -194,24 +597,186 @@ impl World {
     }
 }
 
-
 impl Draw for World {
     fn draw(&self) -> DrawCommand {
-        DrawCommand::List(
-            self.rooms.iter().map(|room| { room.draw() }).chain(
-                self.passages.iter().map(|passage|{passage.draw()})
-            ).collect()
-        )
+        DrawCommand::List(self.visible_rooms().map(|room| { room.draw() }).collect())
+    }
+}
+
+
+
+/// How much the player knows about a room or passage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// Never seen, not drawn
+    Hidden,
+
+    /// Seen from a distance, drawn darkened
+    Seen,
+
+    /// Entered, for rooms this means the rooms around it have been generated
+    Explored,
+}
+
+
+impl Visibility {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Visibility::Hidden => "hidden",
+            Visibility::Seen => "seen",
+            Visibility::Explored => "explored",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Visibility> {
+        Some(match name {
+            "hidden" => Visibility::Hidden,
+            "seen" => Visibility::Seen,
+            "explored" => Visibility::Explored,
+
+            _ => return None
+        })
     }
 }
 
 
-struct Room {
+
+/// A world as it can be saved, the layout is generated again from the seed and the order the rooms were explored in.
+///
+/// ```text
+/// seed 1234
+/// explored 0 2 1
+/// rooms explored explored explored seen hidden
+/// passages explored explored seen seen
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldSnapshot {
+    pub seed: u64,
+    pub explored: Vec<usize>,
+
+    pub rooms: Vec<Visibility>,
+    pub passages: Vec<Visibility>,
+}
+
+
+impl WorldSnapshot {
+    pub fn parse(source: &str) -> Result<WorldSnapshot, String> {
+        let mut seed = None;
+        let mut explored = Vec::new();
+        let mut rooms = Vec::new();
+        let mut passages = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let error = |message: &str| { format!("line {}: {}", number + 1, message) };
+
+            let mut words = line.split_whitespace();
+
+            let visibilities = |words: ::std::str::SplitWhitespace| -> Result<Vec<Visibility>, String> {
+                words.map(|word| { Visibility::from_name(word).ok_or_else(|| { error("unknown visibility") }) })
+                    .collect()
+            };
+
+            match words.next() {
+                None => (),
+
+                Some("seed") => {
+                    seed = Some(words.next().and_then(|word| { word.parse().ok() })
+                        .ok_or_else(|| { error("expected a number") })?);
+                }
+
+                Some("explored") => {
+                    explored = words.map(|word| { word.parse().map_err(|_| { error("expected a room") }) })
+                        .collect::<Result<_, _>>()?;
+                }
+
+                Some("rooms") => rooms = visibilities(words)?,
+                Some("passages") => passages = visibilities(words)?,
+
+                Some(other) => return Err(error(&format!("unknown entry `{}`", other))),
+            }
+        }
+
+        Ok(WorldSnapshot {
+            seed: seed.ok_or_else(|| { "missing seed".to_string() })?,
+            explored,
+            rooms,
+            passages,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<WorldSnapshot, String> {
+        let mut source = String::new();
+
+        File::open(path)
+            .and_then(|mut file| { file.read_to_string(&mut source) })
+            .map_err(|e| { format!("{}: {}", path, e) })?;
+
+        Self::parse(&source).map_err(|e| { format!("{}: {}", path, e) })
+    }
+}
+
+
+/// Snapshots are written the same way they are parsed
+impl fmt::Display for WorldSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let explored: Vec<String> = self.explored.iter().map(|room| { room.to_string() }).collect();
+        let rooms: Vec<&str> = self.rooms.iter().map(|visibility| { visibility.name() }).collect();
+        let passages: Vec<&str> = self.passages.iter().map(|visibility| { visibility.name() }).collect();
+
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "explored {}", explored.join(" "))?;
+        writeln!(f, "rooms {}", rooms.join(" "))?;
+        writeln!(f, "passages {}", passages.join(" "))
+    }
+}
+
+
+/// A ray hitting one of the boxes in the world
+pub struct WallHit {
+    pub distance: f64,
+    pub normal: Vector3,
+    pub point: Vector3,
+
+    room: RoomRef,
+    wall: usize,
+}
+
+
+#[derive(Copy, Clone, Debug)]
+enum RoomRef {
+    Room(usize),
+    Passage(usize),
+}
+
+
+/// Accumulated damage around a point on the walls of a room
+struct Impact {
+    point: Vector2,
+    damage: f64,
+}
+
+
+pub struct Room {
     boxes: Vec<BoundingBox>,
 
     floor: Rectangle,
 
-    explored: bool
+    visibility: Visibility,
+
+    impacts: Vec<Impact>,
+
+    /// Where holes have been blown through the walls, letting the rooms behind be seen
+    holes: Vec<Rectangle>,
+
+    /// The boxes as they are drawn, without the faces hidden between them
+    mesh: Mesh,
+
+    /// The boxes or visibility changed since the mesh was built
+    dirty: bool,
+
+    /// Lights hanging on the walls, only lighting this room
+    torches: Vec<PointLight>,
+    flames: Mesh,
 }
 
 
@@ -251,8 +816,101 @@ impl Room {
 
             floor,
 
-            explored: false,
+            visibility: Visibility::Hidden,
+
+            impacts: Vec::new(),
+
+            holes: Vec::new(),
+
+            mesh: Mesh::new(),
+            dirty: true,
+
+            torches: Vec::new(),
+            flames: Mesh::glowing(),
+        }
+    }
+
+
+    /// Hang torches evenly along every wall
+    fn place_torches(&mut self) {
+        let floor = self.floor;
+        let size = floor.size();
+
+        let count = |length: f64| { ((length / TORCH_SPACING) as usize).max(1) };
+        let (columns, rows) = (count(size.x), count(size.y));
+
+        let mut spots = Vec::new();
+
+        for i in 0..columns {
+            let x = floor.min.x + (i as f64 + 0.5) * size.x / columns as f64;
+            spots.push(Vector2::new(x, floor.min.y + TORCH_OFFSET));
+            spots.push(Vector2::new(x, floor.max.y - TORCH_OFFSET));
+        }
+
+        for i in 0..rows {
+            let y = floor.min.y + (i as f64 + 0.5) * size.y / rows as f64;
+            spots.push(Vector2::new(floor.min.x + TORCH_OFFSET, y));
+            spots.push(Vector2::new(floor.max.x - TORCH_OFFSET, y));
+        }
+
+        self.torches = spots.into_iter()
+            .map(|spot| {
+                PointLight {
+                    position: Vector3::new(spot.x, TORCH_HEIGHT, spot.y),
+                    color: TORCH_COLOR,
+                    radius: TORCH_RADIUS,
+                }
+            })
+            .collect();
+
+        self.dirty = true;
+    }
+
+    pub fn torches(&self) -> &[PointLight] {
+        &self.torches
+    }
+
+    pub fn flames(&self) -> &Mesh {
+        &self.flames
+    }
+
+
+    /// Reveal the room from a distance, unless it has been entered already
+    fn see(&mut self) {
+        if self.visibility == Visibility::Hidden {
+            self.visibility = Visibility::Seen;
+            self.dirty = true;
+        }
+    }
+
+
+    /// The boxes as they are drawn
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    fn rebuild_mesh(&mut self) {
+        self.mesh = Mesh::from_boxes(&self.boxes, &self.torches);
+        self.flames = Mesh::glowing();
+
+        for torch in self.torches.iter() {
+            let mut flame = BoundingBox::cube(torch.position, FLAME_SIZE);
+            flame.color = Some(Color::new(TORCH_COLOR[0] as f64, TORCH_COLOR[1] as f64, TORCH_COLOR[2] as f64, 1.0));
+
+            self.flames.push_box(&flame, &[false; 6]);
         }
+
+        if self.visibility == Visibility::Seen {
+            self.mesh.darken(SEEN_BRIGHTNESS);
+            self.flames.darken(SEEN_BRIGHTNESS);
+        }
+
+        // The sun and torches don't move, only flashes and fog are added every frame
+        let lighting = Lighting::new();
+        self.mesh.light(&lighting, &self.torches);
+        self.flames.light(&lighting, &[]);
+
+        self.dirty = false;
     }
 
 
@@ -280,6 +938,68 @@ impl Room {
                 box_count -= 1;
             }
         }
+
+        // Torches fall with the walls they hang on
+        let floor = self.floor;
+        let walls = &self.boxes[1..];
+        self.torches.retain(|torch| {
+            let spot = behind(floor, Vector2::new(torch.position.x, torch.position.z));
+            walls.iter().any(|wall| { wall.project_y().contains(spot) })
+        });
+
+        self.dirty = true;
+    }
+
+
+    /// Damage a wall at a point, carving a hole through it once the spot has taken enough damage
+    pub fn damage(&mut self, wall: usize, point: Vector3, normal: Vector3, amount: f64) -> bool {
+        // The floor is always the first box and cannot be destroyed
+        if wall == 0 {
+            return false;
+        }
+
+        let spot = Vector2::new(point.x, point.z);
+
+        let index = self.impacts.iter().position(|impact| {
+            let dx = impact.point.x - spot.x;
+            let dy = impact.point.y - spot.y;
+            dx * dx + dy * dy < IMPACT_RADIUS * IMPACT_RADIUS
+        });
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                self.impacts.push(Impact {
+                    point: spot,
+                    damage: 0.0,
+                });
+                self.impacts.len() - 1
+            }
+        };
+
+        self.impacts[index].damage += amount;
+
+        if self.impacts[index].damage < WALL_STRENGTH {
+            return false;
+        }
+
+        let impact = self.impacts.remove(index);
+
+        // Reach through the full thickness of the wall along the normal
+        let depth = 4.0 * WALL_THICKNESS;
+        let size = if normal.x != 0.0 {
+            Vector2::new(depth, HOLE_SIZE)
+        } else if normal.z != 0.0 {
+            Vector2::new(HOLE_SIZE, depth)
+        } else {
+            Vector2::new(HOLE_SIZE, HOLE_SIZE)
+        };
+
+        let hole = Rectangle::centered(impact.point, size);
+        self.cut_walls(hole);
+        self.holes.push(hole);
+
+        true
     }
 
 
@@ -289,12 +1009,264 @@ impl Room {
             max: Vector2::new(self.floor.max.x + WALL_THICKNESS, self.floor.max.y + WALL_THICKNESS),
         }
     }
+
+    /// A box around the floor and walls
+    pub fn bounds(&self) -> BoundingBox {
+        self.area().extrude_y(-1.0, WALL_HEIGHT)
+    }
+
+
+    /// The walls and flames lit by a scene
+    pub fn lit(&self, lighting: &Lighting) -> DrawCommand {
+        DrawCommand::List(vec![
+            self.mesh.lit(lighting, &self.torches),
+            self.flames.lit(lighting, &[]),
+        ])
+    }
+
+    /// How rooms were drawn before they kept meshes, every box with its own eight corners and
+    /// the colours of seen rooms darkened again every frame. Kept for `World::benchmark` to compare against.
+    fn draw_box_by_box(&self) -> DrawCommand {
+        let boxes = self.boxes.iter().map(box_corners);
+
+        if self.visibility == Visibility::Seen {
+            DrawCommand::List(boxes.map(|command| { darken(command, SEEN_BRIGHTNESS) }).collect())
+        } else {
+            DrawCommand::List(boxes.collect())
+        }
+    }
 }
 
 impl Draw for Room {
     fn draw(&self) -> DrawCommand {
-        DrawCommand::List(
-            self.boxes.iter().map(|b| { b.draw() }).collect()
-        )
+        self.lit(&Lighting::new())
     }
-}
\ No newline at end of file
+}
+
+
+/// The opening where a passage meets a room, as the corners of a rectangle standing on the edge of the room's floor
+fn opening(room: &Room, passage: &Room) -> [Vector3; 4] {
+    let (r, p) = (room.floor, passage.floor);
+
+    // Passages start on the edge of the floors they connect
+    let (a, b) = if p.min.x >= r.max.x - WALL_THICKNESS {
+        (Vector2::new(r.max.x, p.min.y), Vector2::new(r.max.x, p.max.y))
+    } else if p.max.x <= r.min.x + WALL_THICKNESS {
+        (Vector2::new(r.min.x, p.min.y), Vector2::new(r.min.x, p.max.y))
+    } else if p.min.y >= r.max.y - WALL_THICKNESS {
+        (Vector2::new(p.min.x, r.max.y), Vector2::new(p.max.x, r.max.y))
+    } else {
+        (Vector2::new(p.min.x, r.min.y), Vector2::new(p.max.x, r.min.y))
+    };
+
+    [
+        Vector3::new(a.x, 0.0, a.y),
+        Vector3::new(b.x, 0.0, b.y),
+        Vector3::new(b.x, WALL_HEIGHT, b.y),
+        Vector3::new(a.x, WALL_HEIGHT, a.y),
+    ]
+}
+
+
+/// The opening of a hole in the walls, as the corners of a rectangle standing on the edge of the floor closest to it
+fn hole_opening(floor: Rectangle, hole: Rectangle) -> [Vector3; 4] {
+    let center = hole.center();
+
+    let edges = [
+        (center.x - floor.min.x).abs(),
+        (center.x - floor.max.x).abs(),
+        (center.y - floor.min.y).abs(),
+        (center.y - floor.max.y).abs(),
+    ];
+
+    let mut closest = 0;
+    for (i, &distance) in edges.iter().enumerate() {
+        if distance < edges[closest] {
+            closest = i;
+        }
+    }
+
+    let (a, b) = match closest {
+        0 => (Vector2::new(floor.min.x, hole.min.y), Vector2::new(floor.min.x, hole.max.y)),
+        1 => (Vector2::new(floor.max.x, hole.min.y), Vector2::new(floor.max.x, hole.max.y)),
+        2 => (Vector2::new(hole.min.x, floor.min.y), Vector2::new(hole.max.x, floor.min.y)),
+        _ => (Vector2::new(hole.min.x, floor.max.y), Vector2::new(hole.max.x, floor.max.y)),
+    };
+
+    [
+        Vector3::new(a.x, 0.0, a.y),
+        Vector3::new(b.x, 0.0, b.y),
+        Vector3::new(b.x, WALL_HEIGHT, b.y),
+        Vector3::new(a.x, WALL_HEIGHT, a.y),
+    ]
+}
+
+
+/// A box as it was drawn before boxes were built into meshes, the eight corners shared between the faces
+fn box_corners(b: &BoundingBox) -> DrawCommand {
+    let vertices = b.corners().iter().enumerate()
+        .map(|(i, corner)| {
+            // Boxes without a colour of their own were coloured by their corners
+            let color = b.color.map_or(
+                [(i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2 & 1) as f32, 1.0],
+                |color| { color.into() }
+            );
+
+            Vertex {
+                position: [corner.x as f32, corner.y as f32, corner.z as f32],
+                color,
+            }
+        })
+        .collect();
+
+    DrawCommand::IndexedVertices {
+        vertices,
+        indices: vec![
+            0, 1, 2, 0, 2, 4, 0, 4, 1,
+            3, 2, 1, 3, 1, 7, 3, 7, 2,
+            5, 4, 7, 5, 7, 1, 5, 1, 4,
+            6, 7, 4, 6, 4, 2, 6, 2, 7,
+        ],
+    }
+}
+
+/// Scale the colour of every vertex in a command
+fn darken(command: DrawCommand, brightness: f32) -> DrawCommand {
+    match command {
+        DrawCommand::IndexedVertices { mut vertices, indices } => {
+            for vertex in vertices.iter_mut() {
+                for channel in vertex.color[..3].iter_mut() {
+                    *channel *= brightness;
+                }
+            }
+
+            DrawCommand::IndexedVertices { vertices, indices }
+        }
+
+        DrawCommand::List(commands) => {
+            DrawCommand::List(commands.into_iter().map(|command| { darken(command, brightness) }).collect())
+        }
+    }
+}
+
+
+/// The middle of the wall behind a point just inside the edge of a floor
+fn behind(floor: Rectangle, point: Vector2) -> Vector2 {
+    let depth = TORCH_OFFSET + 0.5 * WALL_THICKNESS;
+
+    let edges = [
+        (point.x - floor.min.x, Vector2::new(-depth, 0.0)),
+        (floor.max.x - point.x, Vector2::new(depth, 0.0)),
+        (point.y - floor.min.y, Vector2::new(0.0, -depth)),
+        (floor.max.y - point.y, Vector2::new(0.0, depth)),
+    ];
+
+    let mut closest = edges[0];
+    for &edge in edges[1..].iter() {
+        if edge.0 < closest.0 {
+            closest = edge;
+        }
+    }
+
+    point + closest.1
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graphics_3d::{
+        Projection,
+        View,
+    };
+
+
+    /// A world with the start room and one of the rooms around it explored
+    fn explored_world() -> World {
+        let mut world = World::new(3);
+        world.explore(Vector3::new(0.0, 1.0, 0.0));
+
+        let center = world.rooms[1].floor.center();
+        let eye = Vector3::new(center.x, 1.0, center.y);
+        world.explore(eye);
+        world.reveal(eye);
+
+        world
+    }
+
+
+    /// Looking along the x axis from the middle of the start room
+    fn frustum() -> Frustum {
+        let eye = Vector3::new(0.0, 1.5, 0.0);
+
+        Frustum::new(
+            &Projection::Perspective { fov: 60.0, aspect: 1.0, near: 0.1, far: 100.0 },
+            &View::LookAt { eye, target: eye + Vector3::new(1.0, 0.0, 0.0), up: Vector3::new(0.0, 1.0, 0.0) },
+        ).unwrap()
+    }
+
+    fn contains(rooms: &[&Room], room: &Room) -> bool {
+        rooms.iter().any(|&other| { ptr::eq(other, room) })
+    }
+
+
+    #[test]
+    fn rooms_are_seen_through_holes() {
+        // A room behind the wall in front of the eye, without any passage to it
+        let mut world = World::new(1);
+        world.rooms[0].visibility = Visibility::Explored;
+
+        let mut behind = Room::new(Rectangle::centered(Vector2::new(20.0, 0.0), Vector2::new(8.0, 8.0)));
+        behind.visibility = Visibility::Seen;
+        world.rooms.push(behind);
+
+        assert!(!contains(&world.portal_rooms(&frustum()), &world.rooms[1]));
+
+        let hit = world.hit_scan(Vector3::new(0.0, 1.5, 0.0), Vector3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!(world.damage(&hit, WALL_STRENGTH));
+
+        let seen = world.portal_rooms(&frustum());
+        assert!(contains(&seen, &world.rooms[1]));
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_display() {
+        let snapshot = explored_world().snapshot();
+        assert_eq!(WorldSnapshot::parse(&snapshot.to_string()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn restored_world_keeps_layout_and_visibility() {
+        let world = explored_world();
+        let restored = World::restore(&world.snapshot()).unwrap();
+
+        let floors: Vec<_> = world.floors().collect();
+        let restored_floors: Vec<_> = restored.floors().collect();
+
+        assert_eq!(restored_floors.len(), floors.len());
+
+        for (&(a, a_visibility), &(b, b_visibility)) in floors.iter().zip(restored_floors.iter()) {
+            assert_eq!((a.min.x, a.min.y, a.max.x, a.max.y), (b.min.x, b.min.y, b.max.x, b.max.y));
+            assert_eq!(a_visibility, b_visibility);
+        }
+
+        assert_eq!(restored.snapshot(), world.snapshot());
+    }
+
+    #[test]
+    fn restore_rejects_rooms_that_are_never_generated() {
+        let mut snapshot = explored_world().snapshot();
+        snapshot.explored.push(100);
+
+        assert!(World::restore(&snapshot).is_err());
+    }
+
+    #[test]
+    fn parse_unknown_visibility() {
+        let error = WorldSnapshot::parse("seed 1\nrooms explored lit\n").err().unwrap();
+        assert_eq!(error, "line 2: unknown visibility");
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+};
+
+use graphics_3d::glutin::{
+    VirtualKeyCode,
+    MouseButton,
+};
+
+use super::GamepadButton;
+
+
+/// Where the bindings are loaded from at startup
+pub const BINDINGS_PATH: &str = "input.cfg";
+
+/// Used when the config file is missing, a copy of the config file shipped with the game
+const DEFAULT_BINDINGS: &str = include_str!("../../input.cfg");
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    Sprint,
+    Jump,
+
+    Grow,
+    Shrink,
+
+    Fire,
+    Reload,
+    Weapon1,
+    Weapon2,
+    Weapon3,
+
+    PreviousItem,
+    NextItem,
+    UseItem,
+    DropItem,
+
+    ToggleMap,
+    MapZoomIn,
+    MapZoomOut,
+
+    Explore,
+    Respawn,
+    CycleCamera,
+    ReloadBindings,
+    TogglePause,
+    SingleStep,
+    Console,
+
+    Pause,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    MenuSelect,
+    MenuBack,
+}
+
+
+/// The name of every action as it appears in the config file
+const ACTION_NAMES: [(Action, &str); 34] = [
+    (Action::MoveForward, "MoveForward"),
+    (Action::MoveBackward, "MoveBackward"),
+    (Action::MoveLeft, "MoveLeft"),
+    (Action::MoveRight, "MoveRight"),
+    (Action::Sprint, "Sprint"),
+    (Action::Jump, "Jump"),
+
+    (Action::Grow, "Grow"),
+    (Action::Shrink, "Shrink"),
+
+    (Action::Fire, "Fire"),
+    (Action::Reload, "Reload"),
+    (Action::Weapon1, "Weapon1"),
+    (Action::Weapon2, "Weapon2"),
+    (Action::Weapon3, "Weapon3"),
+
+    (Action::PreviousItem, "PreviousItem"),
+    (Action::NextItem, "NextItem"),
+    (Action::UseItem, "UseItem"),
+    (Action::DropItem, "DropItem"),
+
+    (Action::ToggleMap, "ToggleMap"),
+    (Action::MapZoomIn, "MapZoomIn"),
+    (Action::MapZoomOut, "MapZoomOut"),
+
+    (Action::Explore, "Explore"),
+    (Action::Respawn, "Respawn"),
+    (Action::CycleCamera, "CycleCamera"),
+    (Action::ReloadBindings, "ReloadBindings"),
+    (Action::TogglePause, "TogglePause"),
+    (Action::SingleStep, "SingleStep"),
+    (Action::Console, "Console"),
+
+    (Action::Pause, "Pause"),
+    (Action::MenuUp, "MenuUp"),
+    (Action::MenuDown, "MenuDown"),
+    (Action::MenuLeft, "MenuLeft"),
+    (Action::MenuRight, "MenuRight"),
+    (Action::MenuSelect, "MenuSelect"),
+    (Action::MenuBack, "MenuBack"),
+];
+
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES.iter()
+            .find(|&&(_, n)| { n == name })
+            .map(|&(action, _)| { action })
+    }
+}
+
+
+
+/// A physical input that can trigger an action
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+
+impl Binding {
+    /// Parse a binding such as `W`, `LShift`, `Mouse:Left` or `Pad:South`
+    pub fn parse(text: &str) -> Option<Binding> {
+        if let Some(name) = text.strip_prefix("Pad:") {
+            parse_gamepad_button(name).map(Binding::Gamepad)
+        } else if let Some(name) = text.strip_prefix("Mouse:") {
+            let button = match name {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                other => MouseButton::Other(other.parse().ok()?),
+            };
+
+            Some(Binding::Mouse(button))
+        } else {
+            parse_key(text).map(Binding::Key)
+        }
+    }
+}
+
+/// Bindings are written the same way they are parsed
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+
+            Binding::Mouse(MouseButton::Left) => write!(f, "Mouse:Left"),
+            Binding::Mouse(MouseButton::Right) => write!(f, "Mouse:Right"),
+            Binding::Mouse(MouseButton::Middle) => write!(f, "Mouse:Middle"),
+            Binding::Mouse(MouseButton::Other(n)) => write!(f, "Mouse:{}", n),
+
+            Binding::Gamepad(button) => write!(f, "Pad:{:?}", button),
+        }
+    }
+}
+
+
+fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    macro_rules! keys {
+        ($($key:ident),*) => {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None
+            }
+        };
+    }
+
+    keys!(
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Insert, Home, Delete, End, PageDown, PageUp,
+        Left, Up, Right, Down, Back, Return, Space, Tab,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        Add, Subtract, Apostrophe, Backslash, Comma, Equals, Grave, Minus, Period, Semicolon, Slash,
+        LBracket, RBracket, LAlt, RAlt, LControl, RControl, LShift, RShift
+    )
+}
+
+
+fn parse_gamepad_button(name: &str) -> Option<GamepadButton> {
+    macro_rules! buttons {
+        ($($button:ident),*) => {
+            match name {
+                $(stringify!($button) => Some(GamepadButton::$button),)*
+                _ => None
+            }
+        };
+    }
+
+    buttons!(
+        South, East, North, West,
+        LeftBumper, RightBumper, LeftTrigger, RightTrigger,
+        Select, Start, LeftStick, RightStick,
+        DPadUp, DPadDown, DPadLeft, DPadRight
+    )
+}
+
+
+
+/// Maps inputs to actions, an action may have any number of bindings
+#[derive(Clone)]
+pub struct Bindings {
+    bindings: Vec<(Binding, Action)>
+}
+
+
+impl Bindings {
+    /// Parse bindings from lines of the form `Action = Binding, Binding`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(source: &str) -> Result<Bindings, String> {
+        let mut bindings = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let inputs = parts.next()
+                .ok_or_else(|| { format!("line {}: expected `Action = Binding`", number + 1) })?;
+
+            let action = Action::from_name(name)
+                .ok_or_else(|| { format!("line {}: unknown action `{}`", number + 1, name) })?;
+
+            for input in inputs.split(',').map(str::trim).filter(|input| { !input.is_empty() }) {
+                let binding = Binding::parse(input)
+                    .ok_or_else(|| { format!("line {}: unknown input `{}`", number + 1, input) })?;
+
+                // Listing an input twice would trigger the action twice
+                if !bindings.contains(&(binding, action)) {
+                    bindings.push((binding, action));
+                }
+            }
+        }
+
+        Ok(Bindings {
+            bindings
+        })
+    }
+
+
+    pub fn load(path: &str) -> Result<Bindings, String> {
+        let mut source = String::new();
+
+        File::open(path)
+            .and_then(|mut file| { file.read_to_string(&mut source) })
+            .map_err(|e| { format!("{}: {}", path, e) })?;
+
+        Self::parse(&source).map_err(|e| { format!("{}: {}", path, e) })
+    }
+
+
+    /// All actions triggered by an input
+    pub fn actions<'a>(&'a self, binding: Binding) -> impl Iterator<Item=Action> + 'a {
+        self.bindings.iter()
+            .filter(move |&&(b, _)| { b == binding })
+            .map(|&(_, action)| { action })
+    }
+
+    /// All inputs that trigger an action
+    pub fn inputs<'a>(&'a self, action: Action) -> impl Iterator<Item=Binding> + 'a {
+        self.bindings.iter()
+            .filter(move |&&(_, a)| { a == action })
+            .map(|&(binding, _)| { binding })
+    }
+}
+
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Self::parse(DEFAULT_BINDINGS).unwrap()
+    }
+}
+
+
+/// Written as the lines of a config file, one for every action with any inputs
+impl fmt::Display for Bindings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(action, name) in ACTION_NAMES.iter() {
+            let inputs: Vec<String> = self.inputs(action).map(|binding| { binding.to_string() }).collect();
+
+            if !inputs.is_empty() {
+                writeln!(f, "{} = {}", name, inputs.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn parse_valid_lines() {
+        let bindings = Bindings::parse("# Comment\n\nJump = Space\nFire = Mouse:Left, Pad:RightTrigger\n").unwrap();
+
+        assert_eq!(bindings.actions(Binding::Key(VirtualKeyCode::Space)).collect::<Vec<_>>(), vec![Action::Jump]);
+        assert_eq!(
+            bindings.inputs(Action::Fire).collect::<Vec<_>>(),
+            vec![Binding::Mouse(MouseButton::Left), Binding::Gamepad(GamepadButton::RightTrigger)]
+        );
+    }
+
+    #[test]
+    fn parse_unknown_action() {
+        let error = Bindings::parse("Jump = Space\nFly = F").err().unwrap();
+        assert_eq!(error, "line 2: unknown action `Fly`");
+    }
+
+    #[test]
+    fn parse_unknown_input() {
+        let error = Bindings::parse("Jump = Spacebar").err().unwrap();
+        assert_eq!(error, "line 1: unknown input `Spacebar`");
+    }
+
+    #[test]
+    fn parse_missing_equals() {
+        assert!(Bindings::parse("Jump Space").is_err());
+    }
+
+    #[test]
+    fn parse_duplicate_keys() {
+        let bindings = Bindings::parse("Jump = Space, Space\nMenuSelect = Space\nJump = Space").unwrap();
+
+        // Listed again for the same action it counts once, for another action it triggers both
+        assert_eq!(
+            bindings.actions(Binding::Key(VirtualKeyCode::Space)).collect::<Vec<_>>(),
+            vec![Action::Jump, Action::MenuSelect]
+        );
+    }
+
+    #[test]
+    fn binding_round_trips_through_display() {
+        for text in ["W", "LShift", "Mouse:Left", "Mouse:4", "Pad:South"].iter() {
+            assert_eq!(Binding::parse(text).unwrap().to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn bindings_round_trip_through_display() {
+        let bindings = Bindings::default();
+        let parsed = Bindings::parse(&bindings.to_string()).unwrap();
+
+        for &(action, _) in ACTION_NAMES.iter() {
+            assert_eq!(parsed.inputs(action).collect::<Vec<_>>(), bindings.inputs(action).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn default_bindings_parse() {
+        Bindings::default();
+    }
+}
@@ -0,0 +1,105 @@
+use gilrs::{
+    self,
+    Gilrs,
+    EventType,
+    Button,
+};
+
+use super::{
+    InputEvent,
+    Binding,
+    GamepadButton,
+    Axis,
+};
+
+
+/// Translates events from all connected gamepads into input events
+pub struct Gamepads {
+    gilrs: Gilrs
+}
+
+
+impl Gamepads {
+    /// Returns None if gamepads are not supported on this platform
+    pub fn new() -> Option<Gamepads> {
+        match Gilrs::new() {
+            Ok(gilrs) => Some(Gamepads { gilrs }),
+            Err(e) => {
+                println!("Gamepads unavailable: {}", e);
+                None
+            }
+        }
+    }
+
+
+    pub fn poll(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+
+        while let Some(gilrs::Event { event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(button) = translate_button(button) {
+                        events.push(InputEvent::Pressed(Binding::Gamepad(button)));
+                    }
+                }
+
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(button) = translate_button(button) {
+                        events.push(InputEvent::Released(Binding::Gamepad(button)));
+                    }
+                }
+
+                EventType::AxisChanged(axis, value, _) => {
+                    if let Some(axis) = translate_axis(axis) {
+                        events.push(InputEvent::Axis(axis, value as f64));
+                    }
+                }
+
+                _ => ()
+            }
+        }
+
+        events
+    }
+}
+
+
+fn translate_button(button: Button) -> Option<GamepadButton> {
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+
+        _ => return None
+    })
+}
+
+
+fn translate_axis(axis: gilrs::Axis) -> Option<Axis> {
+    Some(match axis {
+        gilrs::Axis::LeftStickX => Axis::LeftX,
+        gilrs::Axis::LeftStickY => Axis::LeftY,
+        gilrs::Axis::RightStickX => Axis::RightX,
+        gilrs::Axis::RightStickY => Axis::RightY,
+        gilrs::Axis::LeftZ => Axis::LeftTrigger,
+        gilrs::Axis::RightZ => Axis::RightTrigger,
+
+        _ => return None
+    })
+}
@@ -0,0 +1,428 @@
+use std::collections::HashSet;
+
+use graphics_3d::glutin::{
+    WindowEvent,
+    DeviceEvent,
+
+    KeyboardInput,
+    ElementState,
+};
+
+
+mod bindings;
+pub use self::bindings::*;
+
+mod gamepad;
+pub use self::gamepad::Gamepads;
+
+
+/// Analog triggers count as pressed above this value
+const TRIGGER_PRESS: f64 = 0.5;
+
+/// Pressed analog triggers are released below this value
+const TRIGGER_RELEASE: f64 = 0.4;
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl Axis {
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+
+
+/// Everything the game reacts to as input, independent of where it came from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Pressed(Binding),
+    Released(Binding),
+
+    /// The new position of an analog axis, sticks range from -1 to 1 with up and right positive
+    Axis(Axis, f64),
+
+    MouseMotion(f64, f64),
+}
+
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<InputEvent> {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state, virtual_keycode: Some(virtual_keycode), ..
+                }, ..
+            } => {
+                Some(match state {
+                    ElementState::Pressed => InputEvent::Pressed(Binding::Key(virtual_keycode)),
+                    ElementState::Released => InputEvent::Released(Binding::Key(virtual_keycode)),
+                })
+            }
+
+            WindowEvent::MouseInput {
+                state, button, ..
+            } => {
+                Some(match state {
+                    ElementState::Pressed => InputEvent::Pressed(Binding::Mouse(button)),
+                    ElementState::Released => InputEvent::Released(Binding::Mouse(button)),
+                })
+            }
+
+            _ => None
+        }
+    }
+
+    pub fn from_device_event(event: &DeviceEvent) -> Option<InputEvent> {
+        match *event {
+            DeviceEvent::MouseMotion { delta: (dx, dy) } => Some(InputEvent::MouseMotion(dx, dy)),
+
+            _ => None
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ActionEvent {
+    Pressed(Action),
+    Released(Action),
+}
+
+
+
+/// How the raw position of an analog stick is turned into a usable value
+#[derive(Copy, Clone, Debug)]
+pub struct StickResponse {
+    /// Deflections smaller than this are ignored
+    pub dead_zone: f64,
+
+    /// Values past the dead zone are raised to this power, larger values give finer control near the center
+    pub exponent: f64,
+}
+
+
+impl StickResponse {
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let magnitude = (x * x + y * y).sqrt();
+
+        if magnitude <= self.dead_zone {
+            return (0.0, 0.0);
+        }
+
+        let scaled = ((magnitude - self.dead_zone) / (1.0 - self.dead_zone)).min(1.0);
+        let curved = scaled.powf(self.exponent);
+
+        (curved * x / magnitude, curved * y / magnitude)
+    }
+}
+
+
+
+/// The state of all inputs, mapped to actions
+pub struct Input {
+    bindings: Bindings,
+    pressed: HashSet<Binding>,
+
+    axes: [f64; 6],
+
+    pub move_response: StickResponse,
+    pub look_response: StickResponse,
+}
+
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Input {
+        Input {
+            bindings,
+            pressed: HashSet::new(),
+
+            axes: [0.0; 6],
+
+            move_response: StickResponse {
+                dead_zone: 0.2,
+                exponent: 1.0,
+            },
+            look_response: StickResponse {
+                dead_zone: 0.15,
+                exponent: 2.0,
+            },
+        }
+    }
+
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
+
+
+    /// Update the input state, returning the actions that were pressed or released
+    pub fn handle(&mut self, event: InputEvent) -> Vec<ActionEvent> {
+        match event {
+            InputEvent::Pressed(binding) => {
+                if self.pressed.insert(binding) {
+                    self.bindings.actions(binding).map(ActionEvent::Pressed).collect()
+                } else {
+                    Vec::new()
+                }
+            }
+
+            InputEvent::Released(binding) => {
+                if self.pressed.remove(&binding) {
+                    let actions: Vec<Action> = self.bindings.actions(binding).collect();
+
+                    // The action may still be held by another input
+                    actions.into_iter()
+                        .filter(|&action| { !self.action_down(action) })
+                        .map(ActionEvent::Released)
+                        .collect()
+                } else {
+                    Vec::new()
+                }
+            }
+
+            InputEvent::Axis(axis, value) => {
+                self.axes[axis.index()] = value;
+
+                // Analog triggers also act as buttons
+                let button = match axis {
+                    Axis::LeftTrigger => GamepadButton::LeftTrigger,
+                    Axis::RightTrigger => GamepadButton::RightTrigger,
+                    _ => return Vec::new(),
+                };
+
+                let binding = Binding::Gamepad(button);
+
+                if value > TRIGGER_PRESS {
+                    self.handle(InputEvent::Pressed(binding))
+                } else if value < TRIGGER_RELEASE {
+                    self.handle(InputEvent::Released(binding))
+                } else {
+                    Vec::new()
+                }
+            }
+
+            InputEvent::MouseMotion(..) => Vec::new(),
+        }
+    }
+
+
+    /// The actions bound to an input, without changing the input state
+    pub fn bound_actions(&self, binding: Binding) -> Vec<Action> {
+        self.bindings.actions(binding).collect()
+    }
+
+
+    pub fn action_down(&self, action: Action) -> bool {
+        self.bindings.inputs(action).any(|binding| { self.pressed.contains(&binding) })
+    }
+
+
+    /// The movement stick as (right, forward)
+    pub fn movement(&self) -> (f64, f64) {
+        self.move_response.apply(self.axis(Axis::LeftX), self.axis(Axis::LeftY))
+    }
+
+    /// The look stick as (right, up)
+    pub fn look(&self) -> (f64, f64) {
+        self.look_response.apply(self.axis(Axis::RightX), self.axis(Axis::RightY))
+    }
+
+    pub fn axis(&self, axis: Axis) -> f64 {
+        self.axes[axis.index()]
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graphics_3d::glutin::{
+        VirtualKeyCode,
+        MouseButton,
+    };
+
+
+    const EPSILON: f64 = 1e-9;
+
+    fn input() -> Input {
+        let bindings = Bindings::parse("\
+            MoveForward = W, Up
+            Jump = Space
+            Fire = Mouse:Left, Pad:RightTrigger
+        ").unwrap();
+
+        Input::new(bindings)
+    }
+
+    fn key(key: VirtualKeyCode) -> Binding {
+        Binding::Key(key)
+    }
+
+
+    #[test]
+    fn press_and_release() {
+        let mut input = input();
+
+        assert_eq!(input.handle(InputEvent::Pressed(key(VirtualKeyCode::Space))), vec![ActionEvent::Pressed(Action::Jump)]);
+        assert!(input.action_down(Action::Jump));
+
+        assert_eq!(input.handle(InputEvent::Released(key(VirtualKeyCode::Space))), vec![ActionEvent::Released(Action::Jump)]);
+        assert!(!input.action_down(Action::Jump));
+    }
+
+    #[test]
+    fn repeated_press_and_release_are_ignored() {
+        let mut input = input();
+
+        input.handle(InputEvent::Pressed(key(VirtualKeyCode::Space)));
+        assert_eq!(input.handle(InputEvent::Pressed(key(VirtualKeyCode::Space))), vec![]);
+
+        input.handle(InputEvent::Released(key(VirtualKeyCode::Space)));
+        assert_eq!(input.handle(InputEvent::Released(key(VirtualKeyCode::Space))), vec![]);
+    }
+
+    #[test]
+    fn unbound_input_does_nothing() {
+        let mut input = input();
+        assert_eq!(input.handle(InputEvent::Pressed(key(VirtualKeyCode::Q))), vec![]);
+    }
+
+    #[test]
+    fn action_held_by_another_input_stays_pressed() {
+        let mut input = input();
+
+        assert_eq!(input.handle(InputEvent::Pressed(key(VirtualKeyCode::W))), vec![ActionEvent::Pressed(Action::MoveForward)]);
+        assert_eq!(input.handle(InputEvent::Pressed(key(VirtualKeyCode::Up))), vec![ActionEvent::Pressed(Action::MoveForward)]);
+
+        assert_eq!(input.handle(InputEvent::Released(key(VirtualKeyCode::W))), vec![]);
+        assert!(input.action_down(Action::MoveForward));
+
+        assert_eq!(input.handle(InputEvent::Released(key(VirtualKeyCode::Up))), vec![ActionEvent::Released(Action::MoveForward)]);
+    }
+
+    #[test]
+    fn mouse_buttons_trigger_actions() {
+        let mut input = input();
+        let button = Binding::Mouse(MouseButton::Left);
+
+        assert_eq!(input.handle(InputEvent::Pressed(button)), vec![ActionEvent::Pressed(Action::Fire)]);
+    }
+
+    #[test]
+    fn mouse_motion_triggers_no_actions() {
+        let mut input = input();
+        assert_eq!(input.handle(InputEvent::MouseMotion(12.0, -3.0)), vec![]);
+    }
+
+    #[test]
+    fn trigger_presses_with_hysteresis() {
+        let mut input = input();
+
+        assert_eq!(input.handle(InputEvent::Axis(Axis::RightTrigger, 0.3)), vec![]);
+        assert_eq!(input.handle(InputEvent::Axis(Axis::RightTrigger, 0.6)), vec![ActionEvent::Pressed(Action::Fire)]);
+
+        // Between the release and press points nothing changes
+        assert_eq!(input.handle(InputEvent::Axis(Axis::RightTrigger, 0.45)), vec![]);
+        assert!(input.action_down(Action::Fire));
+
+        assert_eq!(input.handle(InputEvent::Axis(Axis::RightTrigger, 0.2)), vec![ActionEvent::Released(Action::Fire)]);
+    }
+
+    #[test]
+    fn stick_axes_are_stored() {
+        let mut input = input();
+
+        assert_eq!(input.handle(InputEvent::Axis(Axis::LeftY, 1.0)), vec![]);
+        assert_eq!(input.axis(Axis::LeftY), 1.0);
+
+        let (x, y) = input.movement();
+        assert!(x.abs() < EPSILON && (y - 1.0).abs() < EPSILON);
+    }
+
+
+    #[test]
+    fn stick_inside_dead_zone_is_centered() {
+        let response = StickResponse { dead_zone: 0.2, exponent: 2.0 };
+
+        assert_eq!(response.apply(0.1, -0.1), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stick_at_dead_zone_is_centered() {
+        let response = StickResponse { dead_zone: 0.2, exponent: 2.0 };
+
+        assert_eq!(response.apply(0.2, 0.0), (0.0, 0.0));
+        assert_eq!(response.apply(0.0, -0.2), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stick_just_past_dead_zone_is_small() {
+        let response = StickResponse { dead_zone: 0.2, exponent: 1.0 };
+        let (x, y) = response.apply(0.21, 0.0);
+
+        assert!((x - 0.0125).abs() < EPSILON && y == 0.0);
+    }
+
+    #[test]
+    fn stick_at_full_deflection_is_one() {
+        for &exponent in [1.0, 2.0, 3.0].iter() {
+            let response = StickResponse { dead_zone: 0.2, exponent };
+
+            let (x, y) = response.apply(-1.0, 0.0);
+            assert!((x + 1.0).abs() < EPSILON && y.abs() < EPSILON);
+
+            // Corners of a square stick reach past the circle but are limited to it
+            let (x, y) = response.apply(1.0, 1.0);
+            assert!(((x * x + y * y).sqrt() - 1.0).abs() < EPSILON);
+            assert!((x - y).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn stick_curve_gives_finer_control() {
+        let response = StickResponse { dead_zone: 0.0, exponent: 2.0 };
+        let (x, _) = response.apply(0.5, 0.0);
+
+        assert!((x - 0.25).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,313 @@
+
+use graphics_3d::{
+    trap::Vector3,
+
+    Draw,
+    DrawCommand,
+};
+
+use mesh::Mesh;
+
+
+/// Light reaching every surface, however it faces
+const AMBIENT: [f32; 3] = [0.2, 0.2, 0.24];
+
+/// The direction the main light shines in, down and at an angle so walls facing different ways differ
+const SUN_DIRECTION: (f64, f64, f64) = (0.35, -1.0, 0.6);
+const SUN_COLOR: [f32; 3] = [0.45, 0.44, 0.48];
+
+/// Seconds a flash takes to fade out
+const FLASH_TIME: f64 = 0.08;
+
+/// How thick exponential fog is at its end distance, nearly everything is hidden by then
+const EXPONENTIAL_FOG_REACH: f64 = 4.0;
+
+
+/// A light shining in every direction from a point, fading out with distance
+#[derive(Copy, Clone, Debug)]
+pub struct PointLight {
+    pub position: Vector3,
+    pub color: [f32; 3],
+
+    /// Nothing further away than this is lit
+    pub radius: f64,
+}
+
+
+impl PointLight {
+    /// How strongly a surface facing along a normal is lit, from 0 to 1
+    fn strength(&self, position: Vector3, normal: Vector3) -> f32 {
+        let offset = self.position - position;
+        let distance = offset.dot(offset).sqrt();
+
+        if distance >= self.radius {
+            return 0.0;
+        }
+
+        // Falls smoothly to nothing at the edge of the radius
+        let falloff = (1.0 - distance / self.radius).powi(2);
+        let facing = if distance > 0.0 { normal.dot(offset) / distance } else { 1.0 };
+
+        (falloff * facing.max(0.0)) as f32
+    }
+}
+
+
+
+/// How the fog thickens with distance
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FogMode {
+    Off,
+
+    /// Clear up to the start, then thickening evenly until nothing is seen at the end
+    Linear,
+
+    /// Thickening from the eye outwards, quickly at first and then slowly
+    Exponential,
+}
+
+
+impl FogMode {
+    pub fn from_index(index: usize) -> Option<FogMode> {
+        Some(match index {
+            0 => FogMode::Off,
+            1 => FogMode::Linear,
+            2 => FogMode::Exponential,
+
+            _ => return None
+        })
+    }
+
+    pub fn index(&self) -> usize {
+        match *self {
+            FogMode::Off => 0,
+            FogMode::Linear => 1,
+            FogMode::Exponential => 2,
+        }
+    }
+
+    /// The mode after this one, for cycling through them in a menu
+    pub fn next(&self) -> FogMode {
+        match *self {
+            FogMode::Off => FogMode::Linear,
+            FogMode::Linear => FogMode::Exponential,
+            FogMode::Exponential => FogMode::Off,
+        }
+    }
+}
+
+
+
+/// Surfaces fading towards a colour the further they are from the eye
+#[derive(Copy, Clone, Debug)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: [f32; 3],
+
+    /// Distances where linear fog starts, and where all fog hides everything behind it
+    pub start: f64,
+    pub end: f64,
+}
+
+
+impl Fog {
+    pub fn off() -> Fog {
+        Fog {
+            mode: FogMode::Off,
+            color: [0.0; 3],
+            start: 0.0,
+            end: 0.0,
+        }
+    }
+
+
+    /// How much of a surface at a distance is hidden by the fog, from 0 to 1
+    pub fn amount(&self, distance: f64) -> f32 {
+        let amount = match self.mode {
+            FogMode::Off => 0.0,
+
+            FogMode::Linear => {
+                if distance >= self.end {
+                    1.0
+                } else {
+                    (distance - self.start) / (self.end - self.start)
+                }
+            }
+
+            FogMode::Exponential => 1.0 - (-EXPONENTIAL_FOG_REACH * distance / self.end).exp(),
+        };
+
+        amount.clamp(0.0, 1.0) as f32
+    }
+
+    /// Blend a colour towards the fog
+    pub fn apply(&self, color: [f32; 4], distance: f64) -> [f32; 4] {
+        let amount = self.amount(distance);
+
+        let mut fogged = color;
+        for (channel, fog) in fogged.iter_mut().zip(self.color.iter()) {
+            *channel += amount * (fog - *channel);
+        }
+
+        fogged
+    }
+}
+
+
+
+/// The lights in a scene
+pub struct Lighting {
+    pub ambient: [f32; 3],
+
+    /// Pointing the way the light travels
+    pub sun_direction: Vector3,
+    pub sun_color: [f32; 3],
+
+    /// Short lights that fade out, with the seconds they have left
+    flashes: Vec<(PointLight, f64)>,
+
+    pub fog: Fog,
+
+    /// Where the scene is seen from, the fog thickens away from here
+    pub eye: Vector3,
+}
+
+
+impl Lighting {
+    pub fn new() -> Lighting {
+        let (x, y, z) = SUN_DIRECTION;
+
+        Lighting {
+            ambient: AMBIENT,
+
+            sun_direction: Vector3::new(x, y, z).normal(),
+            sun_color: SUN_COLOR,
+
+            flashes: Vec::new(),
+
+            fog: Fog::off(),
+            eye: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+
+    pub fn with_fog(mut self, fog: Fog) -> Lighting {
+        self.fog = fog;
+        self
+    }
+
+
+    /// Light up the surroundings for a moment
+    pub fn flash(&mut self, light: PointLight) {
+        self.flashes.push((light, FLASH_TIME));
+    }
+
+    /// Fade out the flashes
+    pub fn update(&mut self, dt: f64) {
+        for flash in self.flashes.iter_mut() {
+            flash.1 -= dt;
+        }
+
+        self.flashes.retain(|&(_, time)| { time > 0.0 });
+    }
+
+
+    /// The colour of a surface at a position facing along a normal, lit by the scene and some extra lights
+    pub fn shade(&self, color: [f32; 4], position: Vector3, normal: Vector3, lights: &[PointLight]) -> [f32; 4] {
+        let mut light = self.steady_light(position, normal, lights);
+        self.add_flashes(&mut light, position, normal);
+
+        self.fogged(multiply(color, light), position)
+    }
+
+    /// The light on a surface from the sun and some extra lights, which stays the same from frame to frame
+    pub fn steady_light(&self, position: Vector3, normal: Vector3, lights: &[PointLight]) -> [f32; 3] {
+        let facing = normal.dot(-1.0 * self.sun_direction).max(0.0) as f32;
+
+        let mut light = [0.0; 3];
+        for (channel, (ambient, sun)) in light.iter_mut().zip(self.ambient.iter().zip(self.sun_color.iter())) {
+            *channel = ambient + facing * sun;
+        }
+
+        add_lights(&mut light, lights.iter().map(|light| { (light, 1.0) }), position, normal);
+
+        light
+    }
+
+    /// Whether the lighting changes from frame to frame, with a flash fading or fog that depends on the eye
+    pub fn changing(&self) -> bool {
+        !self.flashes.is_empty() || self.fog.mode != FogMode::Off
+    }
+
+    /// Add the flashes and fog to a colour already lit by the steady light, given the colour it had unlit
+    pub fn shade_lit(&self, lit: [f32; 4], color: [f32; 4], position: Vector3, normal: Vector3) -> [f32; 4] {
+        let mut flash = [0.0; 3];
+        self.add_flashes(&mut flash, position, normal);
+
+        let mut shaded = multiply(color, flash);
+        for (channel, lit) in shaded.iter_mut().zip(lit.iter()) {
+            *channel += lit;
+        }
+        shaded[3] = lit[3];
+
+        self.fogged(shaded, position)
+    }
+
+    fn add_flashes(&self, light: &mut [f32; 3], position: Vector3, normal: Vector3) {
+        let flashes = self.flashes.iter().map(|&(ref light, time)| { (light, (time / FLASH_TIME) as f32) });
+        add_lights(light, flashes, position, normal);
+    }
+
+    /// The colour of something at a position, seen through the fog
+    pub fn fogged(&self, color: [f32; 4], position: Vector3) -> [f32; 4] {
+        if self.fog.mode == FogMode::Off {
+            return color;
+        }
+
+        let offset = position - self.eye;
+        self.fog.apply(color, offset.dot(offset).sqrt())
+    }
+}
+
+
+
+/// Add point lights to the light on a surface, each with a brightness from 0 to 1
+fn add_lights<'a, I>(light: &mut [f32; 3], points: I, position: Vector3, normal: Vector3)
+    where I: Iterator<Item=(&'a PointLight, f32)>
+{
+    for (point, brightness) in points {
+        let strength = brightness * point.strength(position, normal);
+
+        if strength > 0.0 {
+            for (channel, color) in light.iter_mut().zip(point.color.iter()) {
+                *channel += strength * color;
+            }
+        }
+    }
+}
+
+/// A colour lit by a light, keeping its alpha
+pub fn multiply(color: [f32; 4], light: [f32; 3]) -> [f32; 4] {
+    let mut lit = color;
+    for (channel, light) in lit.iter_mut().zip(light.iter()) {
+        *channel *= light;
+    }
+
+    lit
+}
+
+
+
+/// A mesh drawn with the lights of a scene, and lights of its own
+pub struct Lit<'a> {
+    pub mesh: &'a Mesh,
+    pub lighting: &'a Lighting,
+    pub lights: &'a [PointLight],
+}
+
+
+impl<'a> Draw for Lit<'a> {
+    fn draw(&self) -> DrawCommand {
+        self.mesh.lit(self.lighting, self.lights)
+    }
+}
@@ -1,11 +1,21 @@
 extern crate graphics_3d;
 
+use std::{
+    env,
+    process,
+    thread,
+    time::Duration,
+};
+
 use graphics_3d::{
     glutin::{
         EventsLoop,
         WindowBuilder,
         ContextBuilder,
 
+        Event,
+        WindowEvent,
+
         CursorState
     },
 
@@ -15,25 +25,170 @@ use graphics_3d::{
 
 extern crate rand;
 
+extern crate gilrs;
+
 mod bounding_box;
 
+mod mesh;
+
+mod lighting;
+
 mod camera;
 
+mod orientation;
+
+mod input;
+use input::Gamepads;
 
 mod game;
 use game::{
-    GameState
+    GameState,
+    StateStack,
 };
 
 mod stopwatch;
 use stopwatch::Stopwatch;
 
+mod scheduler;
+
 
 mod frame_counter;
 use frame_counter::FrameCounter;
 
+mod replay;
+use replay::{
+    Recorder,
+    Replay,
+};
+
+
+const USAGE: &str = "\
+usage: rogue [--seed <seed>] [--record <file>]
+       rogue --replay <file>
+       rogue --verify <file>...
+       rogue [--seed <seed>] --script <file>
+
+    --seed <seed>     generate the world from a seed
+    --record <file>   record all input to a file
+    --replay <file>   play back a recording
+    --verify <file>   play back recordings without a window and check that
+                      the player ends up where the recording ended
+    --script <file>   run console commands from a file without a window";
+
+
+/// How the game was started from the command line
+#[derive(Default)]
+struct Options {
+    seed: Option<u64>,
+    record: Option<String>,
+    replay: Option<String>,
+    verify: Vec<String>,
+    script: Option<String>,
+}
+
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || { args.next().ok_or_else(|| { format!("missing value for {}", arg) }) };
+
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value()?;
+                options.seed = Some(seed.parse().map_err(|_| { format!("invalid seed: {}", seed) })?);
+            }
+            "--record" => options.record = Some(value()?),
+            "--replay" => options.replay = Some(value()?),
+            "--verify" => options.verify.push(value()?),
+            "--script" => options.script = Some(value()?),
+
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+
+/// Run recordings headlessly, returning the exit code
+fn verify(paths: &[String]) -> i32 {
+    let mut code = 0;
+
+    for path in paths {
+        match Replay::load(path) {
+            Ok(replay) => {
+                let position = replay.run_headless().player_position();
+
+                if replay.matches(position) {
+                    println!("{}: ok", path);
+                } else if replay.end.is_none() {
+                    println!("{}: no end position, the recording was never finished", path);
+                    code = 1;
+                } else {
+                    println!("{}: expected to end at {:?}, ended at {:?}", path, replay.end, position);
+                    code = 1;
+                }
+            }
+
+            Err(e) => {
+                println!("{}", e);
+                code = 2;
+            }
+        }
+    }
+
+    code
+}
+
+
+/// Run a console script headlessly, returning the exit code
+fn run_script(path: &str, seed: Option<u64>) -> i32 {
+    let mut game = match seed {
+        Some(seed) => GameState::with_seed(seed),
+        None => GameState::new(),
+    };
+
+    match game.run_script(path) {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+
+            0
+        }
+
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
+
 
 fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        println!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    if !options.verify.is_empty() {
+        process::exit(verify(&options.verify));
+    }
+
+    if let Some(ref path) = options.script {
+        process::exit(run_script(path, options.seed));
+    }
+
+    let replay = options.replay.as_ref().map(|path| {
+        Replay::load(path).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(2);
+        })
+    });
+
+
     let mut events_loop = EventsLoop::new();
 
     let mut display = {
@@ -48,33 +203,109 @@ fn main() {
         Display::new(window, context, &events_loop)
     };
 
-    display.set_cursor_state(CursorState::Grab);
+    let mut game = match (&replay, options.seed) {
+        (Some(replay), _) => GameState::with_seed(replay.seed),
+        (None, Some(seed)) => GameState::with_seed(seed),
+        (None, None) => GameState::new(),
+    };
 
+    if let Some(ref path) = options.record {
+        match Recorder::create(path, game.seed()) {
+            Ok(recorder) => game.start_recording(recorder),
+            Err(e) => println!("Failed to start recording {}: {}", path, e),
+        }
+    }
+
+    // Replays skip the menus, their input goes straight to the game
+    let mut states = if replay.is_some() {
+        StateStack::playing(game)
+    } else {
+        StateStack::new(game)
+    };
+
+    let mut cursor_grabbed = None;
+
+    let mut gamepads = Gamepads::new();
 
-    let mut game = GameState::new();
+    let mut replay_frames = replay.as_ref().map(|replay| { replay.frames.iter() });
 
 
     let mut stopwatch = Stopwatch::new();
 
     let mut frame_counter = FrameCounter::new();
 
-    while game.running() {
+    while states.running() {
         if let Some(fps) = frame_counter.tick() {
-            println!("FPS: {}", fps.ceil());
+            states.game_mut().set_fps(fps);
         }
 
+        let replaying = replay_frames.is_some();
         events_loop.poll_events(|e| {
-            game.handle_event(e);
+            // Input during a replay comes from the recording
+            let window_only = matches!(
+                e,
+                Event::WindowEvent { event: WindowEvent::Resized(..), .. } |
+                Event::WindowEvent { event: WindowEvent::CloseRequested, .. }
+            );
+
+            if !replaying || window_only {
+                states.handle_event(e);
+            }
         });
 
-        let delta_time = stopwatch.tick();
-        game.update(delta_time);
+        let delta_time = if let Some(ref mut frames) = replay_frames {
+            match frames.next() {
+                Some(frame) => {
+                    frame.play(states.game_mut());
+
+                    // Play back at the recorded speed
+                    let elapsed = stopwatch.tick();
+                    if elapsed < frame.delta_time {
+                        let remaining = frame.delta_time - elapsed;
+                        thread::sleep(Duration::new(remaining as u64, (remaining.fract() * 1e9) as u32));
+                    }
+                    stopwatch.tick();
+
+                    frame.delta_time
+                }
+
+                None => break
+            }
+        } else {
+            if let Some(ref mut gamepads) = gamepads {
+                for event in gamepads.poll() {
+                    states.handle_input(event);
+                }
+            }
+
+            stopwatch.tick()
+        };
+
+        states.update(delta_time);
+
+        // The cursor is only captured while playing, menus release it
+        let grab = states.grab_cursor();
+        if cursor_grabbed != Some(grab) {
+            display.set_cursor_state(if grab { CursorState::Grab } else { CursorState::Normal });
+            cursor_grabbed = Some(grab);
+        }
 
         let mut frame = display.render();
 
-        game.draw(&mut frame);
+        states.draw(&mut frame);
 
         display.submit(frame);
     }
+
+    let game = states.game_mut();
+    game.stop_recording();
+
+    if let Some(ref replay) = replay {
+        if replay.matches(game.player_position()) {
+            println!("Replay finished at the recorded position");
+        } else {
+            println!("Replay diverged: expected to end at {:?}, ended at {:?}", replay.end, game.player_position());
+        }
+    }
 }
 
@@ -0,0 +1,424 @@
+
+use graphics_3d::{
+    trap::Vector3,
+
+    Draw,
+    DrawCommand,
+    Vertex,
+};
+
+use bounding_box::BoundingBox;
+
+use lighting::{
+    self,
+    Lighting,
+    PointLight,
+};
+
+
+/// Colour of boxes that don't have one of their own
+const DEFAULT_COLOR: [f32; 4] = [0.55, 0.5, 0.45, 1.0];
+
+/// Faces a light reaches are split into quads no longer than this along either side.
+/// Lights are worked out at the corners, so a light in the middle of a big face would be missed.
+/// Torches fade out over several units, so quads this size still show them falling off.
+const MAX_QUAD_SIZE: f64 = 3.0;
+
+
+/// The faces of a box as the axis they face along, whether they are on the maximum side, and their corners
+/// going around the face. Corners are numbered like `BoundingBox::corners`.
+const FACES: [(usize, bool, [usize; 4]); 6] = [
+    (0, false, [0, 2, 6, 4]),
+    (0, true, [3, 1, 5, 7]),
+    (1, false, [0, 4, 5, 1]),
+    (1, true, [3, 7, 6, 2]),
+    (2, false, [0, 1, 3, 2]),
+    (2, true, [5, 4, 6, 7]),
+];
+
+
+
+/// Triangles built once and drawn many times.
+/// Every face has its own corners, so each vertex knows which way its face points.
+#[derive(Clone)]
+pub struct Mesh {
+    vertices: Vec<Vertex>,
+    normals: Vec<Vector3>,
+    indices: Vec<u32>,
+
+    /// Drawn at full brightness whatever the lights, like a flame
+    glow: bool,
+
+    /// The vertices lit by the steady lights, kept until the mesh changes.
+    /// While nothing else lights the scene they are drawn as they are, without being built again every frame.
+    lit_vertices: Option<Vec<Vertex>>,
+}
+
+
+impl Mesh {
+    pub fn new() -> Mesh {
+        Mesh {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+            glow: false,
+            lit_vertices: None,
+        }
+    }
+
+    pub fn glowing() -> Mesh {
+        Mesh {
+            glow: true,
+            ..Mesh::new()
+        }
+    }
+
+    /// Every face of a box
+    pub fn from_box(b: &BoundingBox) -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.push_box(b, &[false; 6]);
+        mesh
+    }
+
+    /// Boxes without the faces that are covered by another of the boxes.
+    /// Faces reached by any of the lights are split up so the light can fall across them.
+    pub fn from_boxes(boxes: &[BoundingBox], lights: &[PointLight]) -> Mesh {
+        let mut mesh = Mesh::new();
+
+        for (i, b) in boxes.iter().enumerate() {
+            let mut hidden = [false; 6];
+
+            for (face, &(axis, max, _)) in FACES.iter().enumerate() {
+                hidden[face] = boxes.iter().enumerate()
+                    .any(|(j, other)| { i != j && covers(other, b, axis, max) });
+            }
+
+            mesh.push_faces(b, &hidden, lights);
+        }
+
+        mesh
+    }
+
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+
+    /// Add the faces of a box that aren't hidden, in the order of `FACES`
+    pub fn push_box(&mut self, b: &BoundingBox, hidden: &[bool; 6]) {
+        self.push_faces(b, hidden, &[]);
+    }
+
+    /// Add the faces of a box that aren't hidden.
+    /// Faces reached by any of the lights are a grid of quads no bigger than `MAX_QUAD_SIZE`, the rest are one quad.
+    fn push_faces(&mut self, b: &BoundingBox, hidden: &[bool; 6], lights: &[PointLight]) {
+        self.lit_vertices = None;
+
+        let corners = b.corners();
+        let color = b.color.map_or(DEFAULT_COLOR, |color| { color.into() });
+
+        for (_, &(axis, max, quad)) in FACES.iter().enumerate().filter(|&(face, _)| { !hidden[face] }) {
+            let sign = if max { 1.0 } else { -1.0 };
+            let normal = Vector3::new(
+                if axis == 0 { sign } else { 0.0 },
+                if axis == 1 { sign } else { 0.0 },
+                if axis == 2 { sign } else { 0.0 },
+            );
+
+            // The sides of the face from its first corner, going around it
+            let origin = corners[quad[0]];
+            let across = corners[quad[1]] - origin;
+            let up = corners[quad[3]] - origin;
+
+            let (columns, rows) = if lights.iter().any(|light| { reaches(light, b, axis, max) }) {
+                (segments(across), segments(up))
+            } else {
+                (1, 1)
+            };
+
+            let first = self.vertices.len() as u32;
+
+            for row in 0..rows + 1 {
+                for column in 0..columns + 1 {
+                    let position = origin +
+                        (column as f64 / columns as f64) * across +
+                        (row as f64 / rows as f64) * up;
+
+                    self.vertices.push(Vertex {
+                        position: [position.x as f32, position.y as f32, position.z as f32],
+                        color,
+                    });
+                    self.normals.push(normal);
+                }
+            }
+
+            for row in 0..rows {
+                for column in 0..columns {
+                    let corner = first + row * (columns + 1) + column;
+                    let quad = [corner, corner + 1, corner + columns + 2, corner + columns + 1];
+
+                    self.indices.extend([0, 1, 2, 0, 2, 3].iter().map(|&index| { quad[index] }));
+                }
+            }
+        }
+    }
+
+
+    /// Scale the colour of every vertex
+    pub fn darken(&mut self, brightness: f32) {
+        self.lit_vertices = None;
+
+        for vertex in self.vertices.iter_mut() {
+            for channel in vertex.color[..3].iter_mut() {
+                *channel *= brightness;
+            }
+        }
+    }
+
+
+    /// Light the mesh ahead of time with the steady lights of a scene and some extra lights,
+    /// only the flashes and fog are added when it is drawn
+    pub fn light(&mut self, lighting: &Lighting, lights: &[PointLight]) {
+        let vertices = self.vertices.iter().zip(self.normals.iter())
+            .map(|(vertex, &normal)| {
+                if self.glow {
+                    *vertex
+                } else {
+                    Vertex {
+                        color: lighting::multiply(vertex.color, lighting.steady_light(position(vertex), normal, lights)),
+                        ..*vertex
+                    }
+                }
+            })
+            .collect();
+
+        self.lit_vertices = Some(vertices);
+    }
+
+
+    /// The triangles with their colours lit by the lights of a scene and some extra lights.
+    /// A mesh that was lit ahead of time keeps its own steady light and extra lights.
+    pub fn lit(&self, lighting: &Lighting, lights: &[PointLight]) -> DrawCommand {
+        let vertices = match self.lit_vertices {
+            // Nothing to add to the steady light, the finished vertices are only copied
+            Some(ref lit) if !lighting.changing() => lit.clone(),
+
+            _ => {
+                self.vertices.iter().zip(self.normals.iter()).enumerate()
+                    .map(|(i, (vertex, &normal))| {
+                        let lit = self.lit_vertices.as_ref().map(|lit| { lit[i].color });
+
+                        let color = match lit {
+                            // Glowing surfaces aren't lit, but still fade into the fog
+                            Some(lit) if self.glow => lighting.fogged(lit, position(vertex)),
+                            None if self.glow => lighting.fogged(vertex.color, position(vertex)),
+
+                            Some(lit) => lighting.shade_lit(lit, vertex.color, position(vertex), normal),
+                            None => lighting.shade(vertex.color, position(vertex), normal, lights),
+                        };
+
+                        Vertex {
+                            color,
+                            ..*vertex
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        DrawCommand::IndexedVertices {
+            vertices,
+            indices: self.indices.clone(),
+        }
+    }
+}
+
+
+/// Lit by the default lights
+impl Draw for Mesh {
+    fn draw(&self) -> DrawCommand {
+        self.lit(&Lighting::new(), &[])
+    }
+}
+
+
+
+/// Whether a box covers a whole face of another box, by touching it or reaching through it
+fn covers(cover: &BoundingBox, b: &BoundingBox, axis: usize, max: bool) -> bool {
+    // The face has to be inside the cover along the other two axes
+    for other in (0..3).filter(|&other| { other != axis }) {
+        if get(cover.min, other) > get(b.min, other) || get(cover.max, other) < get(b.max, other) {
+            return false;
+        }
+    }
+
+    // And the cover has to reach from the face outwards
+    if max {
+        let face = get(b.max, axis);
+        get(cover.min, axis) <= face && face < get(cover.max, axis)
+    } else {
+        let face = get(b.min, axis);
+        get(cover.min, axis) < face && face <= get(cover.max, axis)
+    }
+}
+
+
+/// Whether a light shines on any part of a face of a box
+fn reaches(light: &PointLight, b: &BoundingBox, axis: usize, max: bool) -> bool {
+    let face = if max { get(b.max, axis) } else { get(b.min, axis) };
+    let height = get(light.position, axis) - face;
+
+    // Lights behind a face don't reach it
+    if (max && height <= 0.0) || (!max && height >= 0.0) {
+        return false;
+    }
+
+    // The closest point on the face to the light
+    let mut distance = height * height;
+    for other in (0..3).filter(|&other| { other != axis }) {
+        let position = get(light.position, other);
+        let offset = position - position.clamp(get(b.min, other), get(b.max, other));
+        distance += offset * offset;
+    }
+
+    distance < light.radius * light.radius
+}
+
+
+/// How many quads a side of a face is split into
+fn segments(side: Vector3) -> u32 {
+    (side.dot(side).sqrt() / MAX_QUAD_SIZE).ceil().max(1.0) as u32
+}
+
+
+fn position(vertex: &Vertex) -> Vector3 {
+    let p = vertex.position;
+    Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64)
+}
+
+
+fn get(v: Vector3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lighting::{
+        Fog,
+        FogMode,
+    };
+
+
+    fn colors(command: DrawCommand) -> Vec<[f32; 4]> {
+        match command {
+            DrawCommand::IndexedVertices { vertices, .. } => vertices.iter().map(|vertex| { vertex.color }).collect(),
+            _ => panic!("expected vertices"),
+        }
+    }
+
+    fn assert_same_colors(a: &[[f32; 4]], b: &[[f32; 4]]) {
+        assert_eq!(a.len(), b.len());
+
+        for (a, b) in a.iter().zip(b.iter()) {
+            for (a, b) in a.iter().zip(b.iter()) {
+                assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+            }
+        }
+    }
+
+
+    #[test]
+    fn lit_ahead_of_time_matches_lit_every_frame() {
+        let b = BoundingBox::cube(Vector3::new(0.0, 1.0, 0.0), 2.0);
+        let torch = PointLight {
+            position: Vector3::new(2.0, 1.5, 0.5),
+            color: [1.0, 0.6, 0.3],
+            radius: 6.0,
+        };
+
+        let mut lighting = Lighting::new().with_fog(Fog {
+            mode: FogMode::Linear,
+            color: [0.1; 3],
+            start: 1.0,
+            end: 10.0,
+        });
+        lighting.eye = Vector3::new(4.0, 1.0, 3.0);
+        lighting.flash(PointLight {
+            position: Vector3::new(0.0, 3.0, 2.0),
+            color: [1.0, 0.9, 0.7],
+            radius: 5.0,
+        });
+
+        let mesh = Mesh::from_box(&b);
+        let mut cached = mesh.clone();
+        cached.light(&Lighting::new(), &[torch]);
+
+        assert_same_colors(&colors(cached.lit(&lighting, &[torch])), &colors(mesh.lit(&lighting, &[torch])));
+
+        // Once the flash fades the cached colours are drawn as they are
+        lighting.update(1.0);
+        lighting.fog.mode = FogMode::Off;
+        assert_same_colors(&colors(cached.lit(&lighting, &[torch])), &colors(mesh.lit(&lighting, &[torch])));
+    }
+
+    #[test]
+    fn faces_reached_by_a_light_are_split_into_small_quads() {
+        let b = BoundingBox {
+            min: Vector3::new(0.0, 0.0, 0.0),
+            max: Vector3::new(4.0, 1.0, 0.5),
+            color: None,
+        };
+        let light = |radius: f64| {
+            PointLight {
+                position: Vector3::new(-1.0, 3.0, -1.0),
+                color: [1.0; 3],
+                radius,
+            }
+        };
+
+        // Too far away to reach the box, every face is one quad
+        assert_eq!(Mesh::from_boxes(&[b], &[light(1.0)]).triangle_count(), 2 * 6);
+
+        // 2 by 1 quads on the top and front, 1 by 1 on the end facing the light, one quad on the faces facing away
+        let mesh = Mesh::from_boxes(&[b], &[light(20.0)]);
+        assert_eq!(mesh.triangle_count(), 2 * (2 + 2 + 1 + 3));
+
+        for triangle in mesh.indices.chunks(3) {
+            let corner = |i: usize| { position(&mesh.vertices[triangle[i] as usize]) };
+            let (a, b, c) = (corner(0), corner(1), corner(2));
+
+            // Every triangle is wound like the corners in `FACES`, clockwise seen from outside
+            let normal = mesh.normals[triangle[0] as usize];
+            let facing = (b - a).cross(c - a).dot(normal);
+            assert!(facing < 0.0);
+
+            // Only the faces turned towards the light are split
+            if normal.dot(light(20.0).position - a) > 0.0 {
+                for &(p, q) in [(a, b), (b, c), (c, a)].iter() {
+                    let side = q - p;
+                    assert!(side.dot(side).sqrt() <= MAX_QUAD_SIZE * 2.0f64.sqrt() + 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn changing_the_mesh_drops_the_cached_colors() {
+        let b = BoundingBox::cube(Vector3::new(0.0, 1.0, 0.0), 2.0);
+
+        let mut mesh = Mesh::from_box(&b);
+        mesh.light(&Lighting::new(), &[]);
+        mesh.darken(0.5);
+
+        assert!(mesh.lit_vertices.is_none());
+    }
+}
@@ -0,0 +1,264 @@
+
+use std::ops::Mul;
+
+use graphics_3d::{
+    View,
+
+    trap::Vector3
+};
+
+
+/// Quaternions closer together than this are blended linearly when slerping,
+/// the angle between them is too small to divide by
+const SLERP_THRESHOLD: f64 = 0.9995;
+
+
+/// A rotation stored as a unit quaternion.
+///
+/// Looking along +z with +y up is the identity, like a camera with no yaw or pitch.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Orientation {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+
+impl Orientation {
+    #[allow(dead_code)]
+    pub fn identity() -> Orientation {
+        Orientation {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Rotate around an axis, counter-clockwise when looking down the axis towards the origin
+    pub fn from_axis_angle(axis: Vector3, angle: f64) -> Orientation {
+        let axis = axis.normal();
+        let (sin, cos) = (0.5 * angle).sin_cos();
+
+        Orientation {
+            w: cos,
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+
+    /// Turn by yaw around +y, then tilt up by pitch and lean right by roll
+    pub fn from_euler(yaw: f64, pitch: f64, roll: f64) -> Orientation {
+        Orientation::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), yaw) *
+            Orientation::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), -pitch) *
+            Orientation::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), roll)
+    }
+
+
+    pub fn dot(&self, other: &Orientation) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// The opposite rotation
+    #[allow(dead_code)]
+    pub fn conjugate(&self) -> Orientation {
+        Orientation {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    /// Scale back to unit length, rounding errors build up when many rotations are combined
+    pub fn normalize(&self) -> Orientation {
+        let length = self.dot(self).sqrt();
+
+        Orientation {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+
+    /// Rotate a vector
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let u = Vector3::new(self.x, self.y, self.z);
+        let t = 2.0 * u.cross(v);
+
+        v + self.w * t + u.cross(t)
+    }
+
+    /// Rotate further around an axis in world space
+    #[allow(dead_code)]
+    pub fn rotated(&self, axis: Vector3, angle: f64) -> Orientation {
+        (Orientation::from_axis_angle(axis, angle) * *self).normalize()
+    }
+
+    /// Blend towards another orientation at a constant angular speed, taking the shortest way around
+    #[allow(dead_code)]
+    pub fn slerp(&self, other: &Orientation, t: f64) -> Orientation {
+        let mut other = *other;
+        let mut cos = self.dot(&other);
+
+        // q and -q are the same rotation, go towards whichever is closer
+        if cos < 0.0 {
+            other = Orientation { w: -other.w, x: -other.x, y: -other.y, z: -other.z };
+            cos = -cos;
+        }
+
+        let (a, b) = if cos > SLERP_THRESHOLD {
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+
+        Orientation {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }.normalize()
+    }
+
+
+    pub fn forward(&self) -> Vector3 {
+        self.rotate(Vector3::new(0.0, 0.0, 1.0))
+    }
+
+    pub fn up(&self) -> Vector3 {
+        self.rotate(Vector3::new(0.0, 1.0, 0.0))
+    }
+
+    /// Points to -x when looking along +z, the same side as the forward direction crossed with up
+    pub fn right(&self) -> Vector3 {
+        self.rotate(Vector3::new(-1.0, 0.0, 0.0))
+    }
+
+
+    /// Looking from a point, with the up direction rolled along
+    pub fn view(&self, eye: Vector3) -> View {
+        View::LookAt {
+            eye,
+            target: eye + self.forward(),
+            up: self.up(),
+        }
+    }
+}
+
+
+/// Combine two rotations, the right one is applied first
+impl Mul for Orientation {
+    type Output = Orientation;
+
+    fn mul(self, o: Orientation) -> Orientation {
+        Orientation {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graphics_3d::PI;
+
+    use camera::Camera;
+
+
+    const EPSILON: f64 = 1e-9;
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        let d = a - b;
+        d.dot(d).sqrt() < EPSILON
+    }
+
+    fn close_orientation(a: &Orientation, b: &Orientation) -> bool {
+        (a.w - b.w).abs() < EPSILON && (a.x - b.x).abs() < EPSILON &&
+            (a.y - b.y).abs() < EPSILON && (a.z - b.z).abs() < EPSILON
+    }
+
+
+    #[test]
+    fn forward_matches_camera_direction() {
+        for &(yaw, pitch) in [(0.0, 0.0), (0.3, 0.2), (-2.5, -1.1), (PI * 0.9, 1.4)].iter() {
+            let mut camera = Camera::new(Vector3::new(0.0, 0.0, 0.0));
+            camera.rotate(yaw, pitch);
+
+            let forward = Orientation::from_euler(yaw, pitch, 0.0).forward();
+            assert!(close(forward, camera.direction()), "yaw {} pitch {}", yaw, pitch);
+        }
+    }
+
+    #[test]
+    fn right_is_forward_cross_up_without_roll() {
+        for &(yaw, pitch) in [(0.0, 0.0), (1.0, 0.0), (-0.7, 0.5), (2.0, -0.9)].iter() {
+            let orientation = Orientation::from_euler(yaw, pitch, 0.0);
+            let expected = orientation.forward().cross(Vector3::new(0.0, 1.0, 0.0)).normal();
+
+            assert!(close(orientation.right(), expected), "yaw {} pitch {}", yaw, pitch);
+        }
+    }
+
+    #[test]
+    fn positive_roll_leans_up_to_the_right() {
+        let right = Orientation::from_euler(0.4, 0.2, 0.0).right();
+        let up = Orientation::from_euler(0.4, 0.2, 0.3).up();
+
+        assert!(up.dot(right) > 0.0);
+    }
+
+
+    #[test]
+    fn slerp_returns_endpoints() {
+        let a = Orientation::from_euler(0.2, 0.1, 0.0);
+        let b = Orientation::from_euler(1.3, -0.4, 0.2);
+
+        assert!(close_orientation(&a.slerp(&b, 0.0), &a));
+        assert!(close_orientation(&a.slerp(&b, 1.0), &b));
+    }
+
+    #[test]
+    fn slerp_takes_the_short_path() {
+        let a = Orientation::identity();
+        let quarter = Orientation::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 0.5 * PI);
+
+        // The same rotation with every component negated
+        let b = Orientation { w: -quarter.w, x: -quarter.x, y: -quarter.y, z: -quarter.z };
+        assert!(a.dot(&b) < 0.0);
+
+        let eighth = Orientation::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), 0.25 * PI);
+        assert!(close(a.slerp(&b, 0.5).forward(), eighth.forward()));
+    }
+
+
+    #[test]
+    fn conjugate_undoes_rotation() {
+        let q = Orientation::from_euler(0.8, -0.3, 0.6);
+        assert!(close_orientation(&(q * q.conjugate()), &Orientation::identity()));
+    }
+
+    #[test]
+    fn rotated_stays_unit_length() {
+        let mut q = Orientation::identity();
+
+        for i in 0..10000 {
+            let axis = Vector3::new(1.0, (i % 7) as f64, (i % 3) as f64 - 1.0);
+            q = q.rotated(axis, 0.37);
+        }
+
+        assert!((q.dot(&q) - 1.0).abs() < EPSILON);
+    }
+}
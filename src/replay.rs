@@ -0,0 +1,392 @@
+use std::{
+    fs::File,
+    io::{
+        self,
+        Read,
+        BufWriter,
+        Write,
+    },
+};
+
+use graphics_3d::trap::Vector3;
+
+use input::{
+    InputEvent,
+    Binding,
+    Bindings,
+    Axis,
+};
+
+use game::GameState;
+
+
+/// Final positions further apart than this count as a different outcome
+const POSITION_TOLERANCE: f64 = 1e-6;
+
+
+/// An option that changes what the recorded input does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Setting {
+    Sensitivity(f64),
+    InvertY(bool),
+    Smoothing(bool),
+}
+
+
+impl Setting {
+    /// Parse a setting written as its console variable name and value
+    fn parse(name: &str, value: f64) -> Option<Setting> {
+        Some(match name {
+            "sensitivity" => Setting::Sensitivity(value),
+            "invert_y" => Setting::InvertY(value != 0.0),
+            "smoothing" => Setting::Smoothing(value != 0.0),
+
+            _ => return None
+        })
+    }
+
+    fn apply(&self, game: &mut GameState) {
+        match *self {
+            Setting::Sensitivity(sensitivity) => game.set_mouse_sensitivity(sensitivity),
+            Setting::InvertY(invert_y) => game.set_invert_y(invert_y),
+            Setting::Smoothing(smoothing) => game.set_mouse_smoothing(smoothing),
+        }
+    }
+}
+
+
+/// Writes a session to a file, one line per input event and one per frame.
+/// The bindings and settings in use are written after the seed, and again whenever they change.
+///
+/// ```text
+/// seed 1234
+/// bindings MoveForward = W; Jump = Space, Pad:South
+/// setting sensitivity 0.001
+/// setting invert_y 0
+/// setting smoothing 0
+/// press W
+/// axis LeftX 0.5
+/// motion 3 -1
+/// frame 0.016
+/// command teleport 0 2 0
+/// release W
+/// frame 0.017
+/// end 1.5 1 -2
+/// ```
+pub struct Recorder {
+    writer: BufWriter<File>,
+    error: Option<io::Error>,
+}
+
+
+impl Recorder {
+    pub fn create(path: &str, seed: u64) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "seed {}", seed)?;
+
+        Ok(Recorder {
+            writer,
+            error: None,
+        })
+    }
+
+
+    pub fn input(&mut self, event: InputEvent) {
+        let result = match event {
+            InputEvent::Pressed(binding) => writeln!(self.writer, "press {}", binding),
+            InputEvent::Released(binding) => writeln!(self.writer, "release {}", binding),
+            InputEvent::Axis(axis, value) => writeln!(self.writer, "axis {:?} {}", axis, value),
+            InputEvent::MouseMotion(dx, dy) => writeln!(self.writer, "motion {} {}", dx, dy),
+        };
+
+        self.check(result);
+    }
+
+    /// The bindings in use from now on, with `;` between the lines of the bindings file
+    pub fn bindings(&mut self, bindings: &Bindings) {
+        let lines: Vec<String> = bindings.to_string().lines().map(String::from).collect();
+
+        let result = writeln!(self.writer, "bindings {}", lines.join("; "));
+        self.check(result);
+    }
+
+    pub fn setting(&mut self, setting: Setting) {
+        let result = match setting {
+            Setting::Sensitivity(sensitivity) => writeln!(self.writer, "setting sensitivity {}", sensitivity),
+            Setting::InvertY(invert_y) => writeln!(self.writer, "setting invert_y {}", invert_y as u8),
+            Setting::Smoothing(smoothing) => writeln!(self.writer, "setting smoothing {}", smoothing as u8),
+        };
+
+        self.check(result);
+    }
+
+    /// A line entered into the console, run before the inputs that follow it
+    pub fn command(&mut self, line: &str) {
+        let result = writeln!(self.writer, "command {}", line.trim());
+        self.check(result);
+    }
+
+    /// Mark the end of a frame, the events written since the last frame happened before it
+    pub fn frame(&mut self, delta_time: f64) {
+        let result = writeln!(self.writer, "frame {}", delta_time);
+        self.check(result);
+    }
+
+
+    /// Write where the player ended up, used to check that a replay reproduced the session
+    pub fn finish(mut self, position: Vector3) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        writeln!(self.writer, "end {} {} {}", position.x, position.y, position.z)?;
+        self.writer.flush()
+    }
+
+
+    /// Remember the first error so it can be reported when finishing
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            if self.error.is_none() {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+
+
+pub enum ReplayEvent {
+    Input(InputEvent),
+    Command(String),
+    Bindings(Bindings),
+    Setting(Setting),
+}
+
+
+pub struct ReplayFrame {
+    pub events: Vec<ReplayEvent>,
+    pub delta_time: f64,
+}
+
+
+impl ReplayFrame {
+    /// Feed the recorded events to the game, in the order they happened
+    pub fn play(&self, game: &mut GameState) {
+        for event in self.events.iter() {
+            match *event {
+                ReplayEvent::Input(input) => {
+                    game.handle_input(input);
+                }
+
+                ReplayEvent::Command(ref line) => {
+                    let _ = game.run_command(line);
+                }
+
+                ReplayEvent::Bindings(ref bindings) => game.set_bindings(bindings.clone()),
+
+                ReplayEvent::Setting(setting) => setting.apply(game),
+            }
+        }
+    }
+}
+
+
+/// A recorded session
+pub struct Replay {
+    pub seed: u64,
+    pub frames: Vec<ReplayFrame>,
+
+    /// Where the player was when the recording was stopped
+    pub end: Option<Vector3>,
+}
+
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let mut source = String::new();
+
+        File::open(path)
+            .and_then(|mut file| { file.read_to_string(&mut source) })
+            .map_err(|e| { format!("{}: {}", path, e) })?;
+
+        Self::parse(&source).map_err(|e| { format!("{}: {}", path, e) })
+    }
+
+    pub fn parse(source: &str) -> Result<Replay, String> {
+        let mut seed = None;
+        let mut frames = Vec::new();
+        let mut events = Vec::new();
+        let mut end = None;
+
+        for (number, line) in source.lines().enumerate() {
+            let error = |message: &str| { format!("line {}: {}", number + 1, message) };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            macro_rules! number {
+                ($i:expr) => {
+                    words.get($i).and_then(|word| { word.parse().ok() })
+                        .ok_or_else(|| { error("expected a number") })?
+                };
+            }
+
+            match words.first() {
+                None => (),
+
+                Some(&"seed") => seed = Some(number!(1)),
+
+                Some(&"press") | Some(&"release") => {
+                    // Inputs that can't be written in the bindings file can't be bound to anything, skip them
+                    if let Some(binding) = words.get(1).and_then(|word| { Binding::parse(word) }) {
+                        events.push(ReplayEvent::Input(if words[0] == "press" {
+                            InputEvent::Pressed(binding)
+                        } else {
+                            InputEvent::Released(binding)
+                        }));
+                    }
+                }
+
+                Some(&"axis") => {
+                    let axis = words.get(1).and_then(|word| { parse_axis(word) })
+                        .ok_or_else(|| { error("unknown axis") })?;
+
+                    events.push(ReplayEvent::Input(InputEvent::Axis(axis, number!(2))));
+                }
+
+                Some(&"motion") => events.push(ReplayEvent::Input(InputEvent::MouseMotion(number!(1), number!(2)))),
+
+                Some(&"command") => events.push(ReplayEvent::Command(words[1..].join(" "))),
+
+                Some(&"bindings") => {
+                    let bindings = Bindings::parse(&words[1..].join(" ").replace(';', "\n"))
+                        .map_err(|e| { error(&format!("bindings {}", e)) })?;
+
+                    events.push(ReplayEvent::Bindings(bindings));
+                }
+
+                Some(&"setting") => {
+                    let value = number!(2);
+                    let setting = words.get(1).and_then(|name| { Setting::parse(name, value) })
+                        .ok_or_else(|| { error("unknown setting") })?;
+
+                    events.push(ReplayEvent::Setting(setting));
+                }
+
+                Some(&"frame") => {
+                    frames.push(ReplayFrame {
+                        events: events.split_off(0),
+                        delta_time: number!(1),
+                    });
+                }
+
+                Some(&"end") => end = Some(Vector3::new(number!(1), number!(2), number!(3))),
+
+                Some(other) => return Err(error(&format!("unknown entry `{}`", other))),
+            }
+        }
+
+        Ok(Replay {
+            seed: seed.ok_or_else(|| { "missing seed".to_string() })?,
+            frames,
+            end,
+        })
+    }
+
+
+    /// Play back the session without a window, returning the final state of the game
+    pub fn run_headless(&self) -> GameState {
+        let mut game = GameState::with_seed(self.seed);
+
+        for frame in self.frames.iter() {
+            frame.play(&mut game);
+            game.update(frame.delta_time);
+        }
+
+        game
+    }
+
+
+    /// Check that a final position matches the recorded one.
+    /// Recordings without an end position, from a session that was never finished, have nothing to match.
+    pub fn matches(&self, position: Vector3) -> bool {
+        match self.end {
+            Some(end) => {
+                (end.x - position.x).abs() < POSITION_TOLERANCE &&
+                    (end.y - position.y).abs() < POSITION_TOLERANCE &&
+                    (end.z - position.z).abs() < POSITION_TOLERANCE
+            }
+
+            None => false
+        }
+    }
+}
+
+
+fn parse_axis(name: &str) -> Option<Axis> {
+    Some(match name {
+        "LeftX" => Axis::LeftX,
+        "LeftY" => Axis::LeftY,
+        "RightX" => Axis::RightX,
+        "RightY" => Axis::RightY,
+        "LeftTrigger" => Axis::LeftTrigger,
+        "RightTrigger" => Axis::RightTrigger,
+
+        _ => return None
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// A short walk with its own bindings and settings, both changed partway through
+    const WALK: &str = include_str!("../replays/walk.rec");
+
+
+    #[test]
+    fn recording_replays_to_its_end() {
+        let replay = Replay::parse(WALK).unwrap();
+        let position = replay.run_headless().player_position();
+
+        assert!(replay.matches(position), "ended at {:?}", position);
+    }
+
+    #[test]
+    fn header_is_played_before_the_first_input() {
+        let replay = Replay::parse(WALK).unwrap();
+        let events = &replay.frames[0].events;
+
+        match events[0] {
+            ReplayEvent::Bindings(ref bindings) => {
+                assert_eq!(bindings.actions(Binding::parse("I").unwrap()).count(), 1);
+            }
+            _ => panic!("expected the bindings first"),
+        }
+
+        match events[1] {
+            ReplayEvent::Setting(setting) => assert_eq!(setting, Setting::Sensitivity(0.002)),
+            _ => panic!("expected the sensitivity after the bindings"),
+        }
+    }
+
+    #[test]
+    fn recording_without_end_never_matches() {
+        let replay = Replay::parse("seed 1\nframe 0.01\n").unwrap();
+        assert!(!replay.matches(replay.run_headless().player_position()));
+    }
+
+    #[test]
+    fn unknown_setting() {
+        let error = Replay::parse("seed 1\nsetting gravity 2\n").err().unwrap();
+        assert_eq!(error, "line 2: unknown setting");
+    }
+
+    #[test]
+    fn missing_seed() {
+        assert_eq!(Replay::parse("frame 0.01\n").err().unwrap(), "missing seed");
+    }
+}
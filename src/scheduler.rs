@@ -0,0 +1,95 @@
+/// Splits frame times into fixed simulation steps so the simulation does not depend on the frame rate
+pub struct FixedStep {
+    rate: f64,
+    accumulator: f64,
+
+    /// Frame times are multiplied by this before being simulated
+    pub time_scale: f64,
+
+    /// If the simulation falls this many seconds behind, skip ahead instead of catching up
+    pub max_lag: f64,
+
+    paused: bool,
+    pending_steps: u32,
+}
+
+
+impl FixedStep {
+    /// Create a scheduler running a number of steps per second
+    pub fn new(rate: f64) -> FixedStep {
+        FixedStep {
+            rate,
+            accumulator: 0.0,
+
+            time_scale: 1.0,
+            max_lag: 0.5,
+
+            paused: false,
+            pending_steps: 0,
+        }
+    }
+
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+        self.accumulator = 0.0;
+    }
+
+    pub fn time_step(&self) -> f64 {
+        1.0 / self.rate
+    }
+
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.pending_steps = 0;
+    }
+
+    /// Run exactly one step during the next frame while paused
+    pub fn single_step(&mut self) {
+        if self.paused {
+            self.pending_steps += 1;
+        }
+    }
+
+
+    /// Add the time of a frame, returning the number of steps to simulate
+    pub fn advance(&mut self, dt: f64) -> u32 {
+        if self.paused {
+            let steps = self.pending_steps;
+            self.pending_steps = 0;
+            return steps;
+        }
+
+        self.accumulator += dt * self.time_scale;
+
+        let time_step = self.time_step();
+        let mut steps = 0;
+
+        while self.accumulator > time_step {
+            if self.accumulator > self.max_lag {
+                self.accumulator = 0.0;
+            } else {
+                self.accumulator -= time_step;
+            }
+
+            steps += 1;
+        }
+
+        steps
+    }
+
+
+    /// How far the current time is between the last step and the next, from 0 to 1
+    pub fn alpha(&self) -> f64 {
+        (self.accumulator / self.time_step()).min(1.0)
+    }
+}